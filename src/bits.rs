@@ -0,0 +1,96 @@
+use std::io::{self, BufRead};
+
+pub(crate) fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads values packed least significant bit first, as used by Deflate and
+/// the legacy PKZIP compression methods.
+///
+/// Bytes are only taken from the underlying reader once their bits are
+/// needed, so the reader is left immediately after the last byte used.
+#[derive(Debug)]
+pub(crate) struct BitReader<R> {
+    inner: R,
+    buffer: u64,
+    count: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Read the next `n` bits, where `n` is at most 32
+    pub(crate) fn bits(&mut self, n: u32) -> io::Result<u32> {
+        debug_assert!(n <= 32);
+
+        while self.count < n {
+            let byte = self.next_byte()?;
+            self.buffer |= u64::from(byte) << self.count;
+            self.count += 8;
+        }
+
+        let value = (self.buffer & ((1 << n) - 1)) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+
+        Ok(value)
+    }
+
+    /// Read a single bit
+    pub(crate) fn bit(&mut self) -> io::Result<bool> {
+        Ok(self.bits(1)? == 1)
+    }
+
+    /// Discard the remaining bits of the current byte
+    pub(crate) fn align_to_byte(&mut self) {
+        let partial = self.count % 8;
+        self.buffer >>= partial;
+        self.count -= partial;
+    }
+
+    fn next_byte(&mut self) -> io::Result<u8> {
+        let byte = *self
+            .inner
+            .fill_buf()?
+            .first()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        self.inner.consume(1);
+
+        Ok(byte)
+    }
+}
+
+/// Writes bits least significant first, for building compressed streams in
+/// tests
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    pub(crate) bytes: Vec<u8>,
+    count: usize,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    pub(crate) fn bits(&mut self, value: u32, n: usize) {
+        for i in 0..n {
+            if self.count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (self.count % 8);
+            self.count += 1;
+        }
+    }
+
+    /// Write a Huffman code, which is stored most significant bit first
+    pub(crate) fn code(&mut self, code: u32, n: usize) {
+        for i in (0..n).rev() {
+            self.bits((code >> i) & 1, 1);
+        }
+    }
+}
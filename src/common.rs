@@ -1,7 +1,26 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 pub const LOCAL_FILE_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
 pub const CENTRAL_DIRECTORY_FILE_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x01, 0x02];
-pub const DATA_DESCRIPTOR_SIGNATURE: [u8; 4] = [0x08, 0x07, 0x4b, 0x50];
+pub const DATA_DESCRIPTOR_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x07, 0x08];
 pub const END_CENTRAL_DIRECTORY_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x05, 0x06];
+pub const ZIP64_END_CENTRAL_DIRECTORY_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x06, 0x06];
+pub const ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x06, 0x07];
+
+/// Header ID of the zip64 extended information extra field
+pub const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+/// Header ID of the NTFS extra field, which holds 64-bit timestamps
+pub const NTFS_EXTRA_FIELD_ID: u16 = 0x000a;
+/// Header ID of the extended timestamp extra field, which holds Unix timestamps
+pub const EXTENDED_TIMESTAMP_EXTRA_FIELD_ID: u16 = 0x5455;
+/// Header ID of the Info-ZIP Unix extra field holding the file's UID and GID
+pub const UNIX_OWNER_EXTRA_FIELD_ID: u16 = 0x7875;
+/// Header ID of the Info-ZIP Unicode path extra field
+pub const UNICODE_PATH_EXTRA_FIELD_ID: u16 = 0x7075;
+/// Header ID of the Info-ZIP Unicode comment extra field
+pub const UNICODE_COMMENT_EXTRA_FIELD_ID: u16 = 0x6375;
+/// Header ID of the WinZip AES extra field
+pub const WINZIP_AES_EXTRA_FIELD_ID: u16 = 0x9901;
 
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -75,6 +94,186 @@ impl DateTimeModified {
     pub fn from_u32(b: u32) -> DateTimeModified {
         Self(b)
     }
+
+    /// The raw MS-DOS date and time, with the date in the high 16 bits
+    pub fn to_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Build an MS-DOS date and time from its calendar values.
+    ///
+    /// Returns `None` if the value is out of range or can't be represented,
+    /// which includes years before 1980 or after 2107. Seconds are rounded
+    /// down to an even number, as MS-DOS times only have a 2 second resolution.
+    pub fn from_date_time(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Option<DateTimeModified> {
+        if !(1980..=2107).contains(&year)
+            || !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return None;
+        }
+
+        let date = (u32::from(year - 1980) << 9) | (u32::from(month) << 5) | u32::from(day);
+        let time = (u32::from(hour) << 11) | (u32::from(minute) << 5) | u32::from(second / 2);
+
+        Some(Self((date << 16) | time))
+    }
+
+    /// Convert a [`SystemTime`], treating it as UTC.
+    ///
+    /// Returns `None` if the time is before 1980 or after 2107.
+    pub fn from_system_time(time: SystemTime) -> Option<DateTimeModified> {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => i64::try_from(duration.as_secs()).ok()?,
+            Err(err) => -i64::try_from(err.duration().as_secs()).ok()?,
+        };
+
+        let days = secs.div_euclid(SECS_PER_DAY);
+        let secs_of_day = secs.rem_euclid(SECS_PER_DAY);
+
+        let (year, month, day) = civil_from_days(days);
+
+        Self::from_date_time(
+            u16::try_from(year).ok()?,
+            month,
+            day,
+            (secs_of_day / 3600) as u8,
+            (secs_of_day / 60 % 60) as u8,
+            (secs_of_day % 60) as u8,
+        )
+    }
+
+    /// The year, from 1980 to 2107
+    pub fn year(self) -> u16 {
+        1980 + ((self.0 >> 25) & 0x7f) as u16
+    }
+
+    /// The month, which should be from 1 to 12
+    pub fn month(self) -> u8 {
+        ((self.0 >> 21) & 0xf) as u8
+    }
+
+    /// The day of the month, which should be from 1 to 31
+    pub fn day(self) -> u8 {
+        ((self.0 >> 16) & 0x1f) as u8
+    }
+
+    /// The hour, which should be from 0 to 23
+    pub fn hour(self) -> u8 {
+        ((self.0 >> 11) & 0x1f) as u8
+    }
+
+    /// The minute, which should be from 0 to 59
+    pub fn minute(self) -> u8 {
+        ((self.0 >> 5) & 0x3f) as u8
+    }
+
+    /// The second, which is always even, as MS-DOS times only have a 2 second
+    /// resolution. This should be from 0 to 58.
+    pub fn second(self) -> u8 {
+        ((self.0 & 0x1f) * 2) as u8
+    }
+
+    /// Whether every field is within its valid range. Archives in the wild
+    /// sometimes contain a zeroed out or otherwise garbage date and time.
+    pub fn is_valid(self) -> bool {
+        let month = self.month();
+
+        (1..=12).contains(&month)
+            && (1..=days_in_month(self.year(), month)).contains(&self.day())
+            && self.hour() < 24
+            && self.minute() < 60
+            && self.second() < 60
+    }
+
+    /// Convert to a [`SystemTime`]. MS-DOS times don't store a time zone, so
+    /// this is treated as UTC, though it is typically the local time of the
+    /// machine which created the archive.
+    ///
+    /// Returns `None` if any field is out of range.
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        if !self.is_valid() {
+            return None;
+        }
+
+        let days = days_from_civil(i64::from(self.year()), self.month(), self.day());
+        let secs = days * SECS_PER_DAY
+            + i64::from(self.hour()) * 3600
+            + i64::from(self.minute()) * 60
+            + i64::from(self.second());
+
+        Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+    }
+}
+
+impl Default for DateTimeModified {
+    /// 1980-01-01 00:00:00, the earliest representable MS-DOS date
+    fn default() -> Self {
+        Self(0x0021_0000)
+    }
+}
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of the given proleptic Gregorian date
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// The proleptic Gregorian date `days` after 1970-01-01
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u8;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
 }
 
 #[repr(transparent)]
@@ -82,12 +281,44 @@ impl DateTimeModified {
 pub struct CompressionMethod(pub u16);
 
 impl CompressionMethod {
+    pub const STORED: CompressionMethod = CompressionMethod(0);
+    pub const DEFLATE: CompressionMethod = CompressionMethod(8);
+    pub const ZSTD: CompressionMethod = CompressionMethod(93);
+    pub const XZ: CompressionMethod = CompressionMethod(95);
+    pub const AES: CompressionMethod = CompressionMethod(99);
+
     pub fn name(self) -> CompressionMethodName {
         CompressionMethodName::from_u16(self.0)
     }
+
+    /// Whether files compressed with this method can be decompressed, given
+    /// the enabled features. Decoders registered with a
+    /// [`crate::DecoderRegistry`] aren't taken into account.
+    pub fn is_supported(self) -> bool {
+        let name = self.name();
+
+        matches!(
+            name,
+            CompressionMethodName::None
+                | CompressionMethodName::Shrink
+                | CompressionMethodName::Factor1
+                | CompressionMethodName::Factor2
+                | CompressionMethodName::Factor3
+                | CompressionMethodName::Factor4
+                | CompressionMethodName::Implode
+                | CompressionMethodName::Deflate
+                | CompressionMethodName::EnhancedDeflate
+        ) || (cfg!(feature = "bzip2") && name == CompressionMethodName::Bzip2)
+            || (cfg!(feature = "lzma") && name == CompressionMethodName::Lzma)
+            || (cfg!(feature = "ppmd") && name == CompressionMethodName::PPMd)
+            || (cfg!(feature = "zstd") && name == CompressionMethodName::Zstd)
+            || (cfg!(feature = "xz") && name == CompressionMethodName::Xz)
+            || (cfg!(feature = "aes") && name == CompressionMethodName::Aes)
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum CompressionMethodName {
     None = 0,
     Shrink = 1,
@@ -104,7 +335,12 @@ pub enum CompressionMethodName {
     Lzma = 14,
     IbmTerse = 18,
     IbmLZ77z = 19,
+    Zstd = 93,
+    Xz = 95,
     PPMd = 98,
+    /// WinZip AES encryption, with the real method in the
+    /// [`crate::WinZipAesExtraField`]
+    Aes = 99,
     Unknown,
 }
 
@@ -126,7 +362,10 @@ impl CompressionMethodName {
             14 => CompressionMethodName::Lzma,
             18 => CompressionMethodName::IbmTerse,
             19 => CompressionMethodName::IbmLZ77z,
+            93 => CompressionMethodName::Zstd,
+            95 => CompressionMethodName::Xz,
             98 => CompressionMethodName::PPMd,
+            99 => CompressionMethodName::Aes,
             _ => CompressionMethodName::Unknown,
         }
     }
@@ -152,12 +391,48 @@ impl ZipFlags {
     pub fn has_data_descriptor(&self) -> bool {
         (self.0 & Self::DATA_DESCRIPTOR) != 0
     }
+
+    pub fn is_encrypted(&self) -> bool {
+        (self.0 & Self::ENCRYPTED_FILE) != 0
+    }
+
+    /// Whether the name and comment are encoded as UTF-8 rather than IBM code
+    /// page 437
+    pub fn has_language_encoding(&self) -> bool {
+        (self.0 & Self::LANGUAGE_ENCODING) != 0
+    }
 }
 
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ExternalAttributes(pub u32);
 
+impl ExternalAttributes {
+    /// MS-DOS directory attribute, stored in the low byte
+    pub const MS_DOS_DIRECTORY: u32 = 0x10;
+
+    /// Mask of the file type bits of a Unix mode
+    pub const UNIX_FILE_TYPE: u32 = 0o170000;
+    pub const UNIX_DIRECTORY: u32 = 0o040000;
+    pub const UNIX_REGULAR_FILE: u32 = 0o100000;
+    pub const UNIX_SYMLINK: u32 = 0o120000;
+
+    /// Attributes holding the Unix `mode`, as written by archivers running on
+    /// Unix
+    pub fn from_unix_mode(mode: u32) -> Self {
+        Self(mode << 16)
+    }
+
+    /// The Unix mode, including both the file type and permission bits, held
+    /// in the high 16 bits.
+    ///
+    /// This is only meaningful when the file was created on Unix. See
+    /// [`crate::CentralDirectoryFileHeader::unix_mode`].
+    pub fn unix_mode(self) -> u32 {
+        self.0 >> 16
+    }
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct InternalAttributes(pub u16);
@@ -167,3 +442,51 @@ impl InternalAttributes {
     pub const RESERVED: u16 = 1 << 1;
     pub const CONTROL_FIELD_RECORDS_PRECEDE_LOGICAL_RECORDS: u16 = 1 << 2;
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::DateTimeModified;
+
+    #[test]
+    fn date_time_fields() {
+        let date_time = DateTimeModified::from_date_time(2024, 2, 29, 23, 59, 59).unwrap();
+
+        assert_eq!(date_time.year(), 2024);
+        assert_eq!(date_time.month(), 2);
+        assert_eq!(date_time.day(), 29);
+        assert_eq!(date_time.hour(), 23);
+        assert_eq!(date_time.minute(), 59);
+        assert_eq!(date_time.second(), 58);
+        assert!(date_time.is_valid());
+
+        assert!(DateTimeModified::from_date_time(2023, 2, 29, 0, 0, 0).is_none());
+        assert!(DateTimeModified::from_date_time(1979, 12, 31, 0, 0, 0).is_none());
+        assert!(!DateTimeModified::from_u32(0).is_valid());
+        assert!(DateTimeModified::from_u32(0).to_system_time().is_none());
+    }
+
+    #[test]
+    fn system_time() {
+        // 2001-09-09 01:46:40 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let date_time = DateTimeModified::from_system_time(time).unwrap();
+
+        assert_eq!(
+            (date_time.year(), date_time.month(), date_time.day()),
+            (2001, 9, 9)
+        );
+        assert_eq!(
+            (date_time.hour(), date_time.minute(), date_time.second()),
+            (1, 46, 40)
+        );
+        assert_eq!(date_time.to_system_time(), Some(time));
+
+        assert_eq!(
+            DateTimeModified::default().to_system_time(),
+            Some(UNIX_EPOCH + Duration::from_secs(315_532_800))
+        );
+        assert!(DateTimeModified::from_system_time(UNIX_EPOCH).is_none());
+    }
+}
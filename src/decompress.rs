@@ -0,0 +1,540 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, Read},
+    sync::Arc,
+};
+
+use flate2::bufread::DeflateDecoder;
+
+#[cfg(feature = "aes")]
+use crate::winzip_aes::{AesReader, AesStrength};
+use crate::{
+    bits::invalid_data, common::*, deflate64::Deflate64Decoder, implode::ImplodeDecoder,
+    reduce::ReduceDecoder, shrink::ShrinkDecoder, zipcrypto::ZipCryptoReader, Metadata,
+    ZipParseError, MB,
+};
+
+/// The default for the most memory a PPMd model may use.
+///
+/// The PPMd parameters can ask for up to 256 MiB, which untrusted files could
+/// otherwise make every decoder allocate. Common archivers use far smaller
+/// models, so files asking for more than 64 MiB are rejected unless the limit
+/// is raised.
+pub const DEFAULT_PPMD_MEMORY_LIMIT: usize = 64 * MB;
+
+/// Settings shared by every decoder
+#[derive(Debug, Clone)]
+pub(crate) struct DecoderOptions {
+    pub(crate) ppmd_memory_limit: usize,
+    pub(crate) registry: DecoderRegistry,
+    /// The password of encrypted files
+    pub(crate) password: Option<Vec<u8>>,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        Self {
+            ppmd_memory_limit: DEFAULT_PPMD_MEMORY_LIMIT,
+            registry: DecoderRegistry::default(),
+            password: None,
+        }
+    }
+}
+
+type DecoderFn = dyn for<'a> Fn(&Metadata<'_>, Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>>
+    + Send
+    + Sync;
+
+/// Decoders for compression methods this crate doesn't support itself.
+///
+/// A decoder is given the file's metadata and a reader over exactly its
+/// compressed contents, and returns a reader over the decompressed contents.
+/// Registered decoders are only used for methods where
+/// [`CompressionMethod::is_supported`] is `false`, so they can't replace the
+/// built-in ones.
+///
+/// ```
+/// # use std::io::Read;
+/// # use zip_rs::{CompressionMethod, DecoderRegistry};
+/// /// A made up method which inverts every byte
+/// struct Invert<R>(R);
+///
+/// impl<R: Read> Read for Invert<R> {
+///     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+///         let n = self.0.read(buf)?;
+///         buf[..n].iter_mut().for_each(|byte| *byte = !*byte);
+///         Ok(n)
+///     }
+/// }
+///
+/// let mut decoders = DecoderRegistry::new();
+/// decoders.register(CompressionMethod(0x7f), |_metadata, reader| {
+///     Ok(Box::new(Invert(reader)))
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct DecoderRegistry {
+    decoders: Arc<HashMap<u16, Arc<DecoderFn>>>,
+}
+
+impl DecoderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `decoder` for files compressed with `method`, replacing any decoder
+    /// previously registered for it
+    pub fn register<F>(&mut self, method: CompressionMethod, decoder: F)
+    where
+        F: for<'a> Fn(&Metadata<'_>, Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Arc::make_mut(&mut self.decoders).insert(method.0, Arc::new(decoder));
+    }
+
+    /// Whether a decoder has been registered for `method`
+    pub fn contains(&self, method: CompressionMethod) -> bool {
+        self.decoders.contains_key(&method.0)
+    }
+}
+
+impl fmt::Debug for DecoderRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut methods = self.decoders.keys().collect::<Vec<_>>();
+        methods.sort_unstable();
+
+        f.debug_struct("DecoderRegistry")
+            .field("methods", &methods)
+            .finish()
+    }
+}
+
+/// Wrap `reader`, which yields exactly the compressed contents of a file, in a
+/// decoder yielding its decompressed contents. Encrypted contents are
+/// decrypted first, using the password in `options`.
+///
+/// Decoders only consume as much of `reader` as the compressed stream
+/// requires, so `reader` may be left positioned immediately after the
+/// compressed contents when their length is not known ahead of time.
+pub(crate) fn decoder<'a, R: BufRead + 'a>(
+    metadata: &Metadata,
+    reader: R,
+    options: &DecoderOptions,
+) -> Result<Box<dyn Read + 'a>, ZipParseError> {
+    // WinZip AES is handled like a compression method, so that without the
+    // aes feature such files are left to the registry
+    if !metadata.flags.is_encrypted() || metadata.compression_method == CompressionMethod::AES {
+        return decompressor(metadata, reader, options);
+    }
+
+    if metadata.flags.0 & ZipFlags::STRONG_ENCRYPTION != 0 {
+        return Err(ZipParseError::Generic("strong encryption is unsupported"));
+    }
+
+    let password = options
+        .password
+        .as_deref()
+        .ok_or(ZipParseError::PasswordRequired)?;
+
+    let reader = ZipCryptoReader::new(reader, password, zipcrypto_check_byte(metadata))?;
+
+    decompressor(metadata, reader, options)
+}
+
+/// The byte the ZipCrypto header of a file ends with, which is used to check
+/// the password.
+///
+/// This is the high byte of the CRC-32, unless a data descriptor is used, in
+/// which case the CRC-32 wasn't known when the header was written, and the
+/// high byte of the MS-DOS modification time is used instead.
+fn zipcrypto_check_byte(metadata: &Metadata) -> u8 {
+    if metadata.flags.has_data_descriptor() {
+        (metadata.date_time_modified.to_u32() >> 8) as u8
+    } else {
+        (metadata.crc >> 24) as u8
+    }
+}
+
+/// Wrap `reader`, which yields the decrypted compressed contents of a file, in
+/// a decoder yielding its decompressed contents
+fn decompressor<'a, R: BufRead + 'a>(
+    metadata: &Metadata,
+    reader: R,
+    options: &DecoderOptions,
+) -> Result<Box<dyn Read + 'a>, ZipParseError> {
+    let method = metadata.compression_method;
+
+    if !method.is_supported() {
+        return match options.registry.decoders.get(&method.0) {
+            Some(decoder) => Ok(decoder(metadata, Box::new(reader))?),
+            None => Err(ZipParseError::UnsupportedCompression(method)),
+        };
+    }
+
+    let size = metadata.uncompressed_size;
+    let flags = metadata.flags.0;
+
+    match metadata.compression_method.name() {
+        CompressionMethodName::None => Ok(Box::new(reader)),
+        CompressionMethodName::Shrink => Ok(Box::new(ShrinkDecoder::new(reader, size))),
+        CompressionMethodName::Factor1 => Ok(Box::new(ReduceDecoder::new(reader, 1, size))),
+        CompressionMethodName::Factor2 => Ok(Box::new(ReduceDecoder::new(reader, 2, size))),
+        CompressionMethodName::Factor3 => Ok(Box::new(ReduceDecoder::new(reader, 3, size))),
+        CompressionMethodName::Factor4 => Ok(Box::new(ReduceDecoder::new(reader, 4, size))),
+        CompressionMethodName::Implode => Ok(Box::new(ImplodeDecoder::new(
+            reader,
+            flags & ZipFlags::COMPRESSION_OPTION1 != 0,
+            flags & ZipFlags::COMPRESSION_OPTION2 != 0,
+            size,
+        ))),
+        CompressionMethodName::Deflate => Ok(Box::new(DeflateDecoder::new(reader))),
+        CompressionMethodName::EnhancedDeflate => Ok(Box::new(Deflate64Decoder::new(reader))),
+        #[cfg(feature = "bzip2")]
+        CompressionMethodName::Bzip2 => Ok(Box::new(bzip2::bufread::BzDecoder::new(reader))),
+        #[cfg(feature = "lzma")]
+        CompressionMethodName::Lzma => Ok(Box::new(lzma(metadata, reader)?)),
+        #[cfg(feature = "zstd")]
+        CompressionMethodName::Zstd => {
+            let decoder = zstd::stream::read::Decoder::with_buffer(reader)?;
+
+            // contents may be split into several frames, unless their size is
+            // unknown, in which case the data descriptor follows the first
+            if metadata.flags.has_data_descriptor() && metadata.compressed_size == 0 {
+                Ok(Box::new(decoder.single_frame()))
+            } else {
+                Ok(Box::new(decoder))
+            }
+        }
+        #[cfg(feature = "xz")]
+        CompressionMethodName::Xz => Ok(Box::new(lzma_rust2::XzReader::new(reader, false))),
+        #[cfg(feature = "ppmd")]
+        CompressionMethodName::PPMd => Ok(Box::new(ppmd(metadata, reader, options)?)),
+        #[cfg(feature = "aes")]
+        CompressionMethodName::Aes => aes(metadata, reader, options),
+        _ => Err(ZipParseError::UnsupportedCompression(method)),
+    }
+}
+
+/// Decrypt WinZip AES encrypted contents, then decompress them with the method
+/// stored in the WinZip AES extra field
+#[cfg(feature = "aes")]
+fn aes<'a, R: BufRead + 'a>(
+    metadata: &Metadata,
+    reader: R,
+    options: &DecoderOptions,
+) -> Result<Box<dyn Read + 'a>, ZipParseError> {
+    let malformed = ZipParseError::MalformedExtraField(WINZIP_AES_EXTRA_FIELD_ID);
+
+    let field = metadata
+        .winzip_aes()
+        .ok_or(ZipParseError::Generic("missing winzip aes extra field"))?;
+
+    let strength = match AesStrength::from_u8(field.strength) {
+        Some(strength) if field.compression_method != CompressionMethod::AES => strength,
+        _ => return Err(malformed),
+    };
+
+    let password = options
+        .password
+        .as_deref()
+        .ok_or(ZipParseError::PasswordRequired)?;
+
+    // boxed, as the decompressor is generic over its reader and may itself
+    // call this function
+    let reader: Box<dyn BufRead + 'a> = Box::new(AesReader::new(
+        reader,
+        password,
+        strength,
+        metadata.compressed_size,
+    )?);
+
+    let metadata = Metadata {
+        compression_method: field.compression_method,
+        ..metadata.clone()
+    };
+
+    decompressor(&metadata, reader, options)
+}
+
+/// Size of the LZMA properties header: the `lc`, `lp` and `pb` byte, followed
+/// by the dictionary size
+#[cfg(feature = "lzma")]
+const LZMA_PROPERTIES_LEN: u16 = 5;
+
+/// Read the header preceding LZMA compressed contents, which holds the LZMA SDK
+/// version, the size of the properties and the properties themselves.
+///
+/// Unlike the `.lzma` file format, the uncompressed size is not part of the
+/// header. The stream is instead terminated by an end of stream marker if
+/// [`ZipFlags::COMPRESSION_OPTION1`] is set.
+#[cfg(feature = "lzma")]
+fn lzma<R: BufRead>(
+    metadata: &Metadata,
+    mut reader: R,
+) -> Result<lzma_rust2::LzmaReader<R>, ZipParseError> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+
+    let properties_len = u16::from_le_bytes([header[2], header[3]]);
+
+    if properties_len != LZMA_PROPERTIES_LEN {
+        return Err(ZipParseError::Generic("malformed lzma properties header"));
+    }
+
+    let mut properties = [0; LZMA_PROPERTIES_LEN as usize];
+    reader.read_exact(&mut properties)?;
+
+    let dict_size =
+        u32::from_le_bytes([properties[1], properties[2], properties[3], properties[4]]);
+
+    // an unknown size tells the decoder to stop at the end of stream marker
+    let uncompressed_size = if metadata.flags.0 & ZipFlags::COMPRESSION_OPTION1 != 0 {
+        u64::MAX
+    } else {
+        metadata.uncompressed_size
+    };
+
+    Ok(lzma_rust2::LzmaReader::new_with_props(
+        reader,
+        uncompressed_size,
+        properties[0],
+        dict_size,
+        None,
+    )?)
+}
+
+/// Read the parameters preceding PPMd compressed contents and create a
+/// variant H (PPMd8) decoder from them.
+///
+/// The parameters are a little endian `u16` holding the model order less one
+/// in bits 0-3, the model size in MiB less one in bits 4-11 and the model
+/// restoration method in bits 12-15.
+#[cfg(feature = "ppmd")]
+fn ppmd<R: BufRead>(
+    metadata: &Metadata,
+    mut reader: R,
+    options: &DecoderOptions,
+) -> Result<io::Take<ppmd_rust::Ppmd8Decoder<R>>, ZipParseError> {
+    let mut parameters = [0; 2];
+    reader.read_exact(&mut parameters)?;
+    let parameters = u16::from_le_bytes(parameters);
+
+    let order = u32::from(parameters & 0xf) + 1;
+    let memory_size = (usize::from(parameters >> 4 & 0xff) + 1) * MB;
+    let restore_method = parameters >> 12;
+
+    if order < ppmd_rust::PPMD8_MIN_ORDER || restore_method > 1 {
+        return Err(ZipParseError::Generic("invalid ppmd parameters"));
+    }
+
+    if memory_size > options.ppmd_memory_limit {
+        return Err(ZipParseError::MemoryLimitExceeded {
+            required: memory_size,
+            limit: options.ppmd_memory_limit,
+        });
+    }
+
+    let decoder =
+        ppmd_rust::Ppmd8Decoder::new(reader, order, memory_size as u32, restore_method.into())
+            .map_err(|err| match err {
+                ppmd_rust::Error::IoError(err) => ZipParseError::IoError(err),
+                ppmd_rust::Error::RangeDecoderInitialization => {
+                    ZipParseError::Generic("malformed ppmd stream")
+                }
+                ppmd_rust::Error::InvalidParameter => {
+                    ZipParseError::Generic("invalid ppmd parameters")
+                }
+                ppmd_rust::Error::MemoryAllocation => {
+                    ZipParseError::IoError(io::ErrorKind::OutOfMemory.into())
+                }
+            })?;
+
+    // the end of stream marker is optional, so stop at the uncompressed size
+    Ok(decoder.take(metadata.uncompressed_size))
+}
+
+/// Computes the CRC-32 of everything read through it
+pub(crate) struct Crc32Reader<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> Crc32Reader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// The CRC-32 of the bytes read so far
+    pub(crate) fn crc(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+impl<R: Read> Read for Crc32Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+
+        Ok(n)
+    }
+}
+
+/// Fails rather than reading more than a file's declared size from its
+/// decoder, which would otherwise be free to produce any amount
+#[derive(Debug)]
+pub(crate) struct SizeLimitReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> SizeLimitReader<R> {
+    pub(crate) fn new(inner: R, size: u64) -> Self {
+        Self {
+            inner,
+            remaining: size,
+        }
+    }
+}
+
+impl<R: Read> Read for SizeLimitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+
+        self.remaining = self
+            .remaining
+            .checked_sub(n as u64)
+            .ok_or_else(|| invalid_data("decompressed contents larger than declared size"))?;
+
+        Ok(n)
+    }
+}
+
+/// Checks the decompressed contents of a file against its declared size and
+/// CRC-32, failing at the end of the contents rather than reporting the end of
+/// contents which don't match.
+///
+/// This is for readers handed out directly, which can't check the contents
+/// once the caller is done with them.
+pub(crate) struct CheckedReader<R> {
+    inner: Crc32Reader<SizeLimitReader<R>>,
+    size: u64,
+    crc: Option<u32>,
+    read: u64,
+}
+
+impl<R: Read> CheckedReader<R> {
+    pub(crate) fn new(inner: R, metadata: &Metadata) -> Self {
+        Self {
+            inner: Crc32Reader::new(SizeLimitReader::new(inner, metadata.uncompressed_size)),
+            size: metadata.uncompressed_size,
+            crc: metadata.has_crc().then_some(metadata.crc),
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for CheckedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+
+        if n == 0 && !buf.is_empty() {
+            if self.read != self.size {
+                return Err(invalid_data(
+                    "decompressed contents smaller than declared size",
+                ));
+            }
+
+            if let Some(expected) = self.crc {
+                let found = self.inner.crc();
+
+                if found != expected {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        ZipParseError::CrcMismatch { expected, found },
+                    ));
+                }
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(all(test, feature = "ppmd"))]
+mod test {
+    use std::io::{Read, Write};
+
+    use crate::{CompressionMethod, DateTimeModified, Metadata, ZipFlags, ZipParseError, MB};
+
+    use super::{decoder, DecoderOptions};
+
+    #[test]
+    fn ppmd() {
+        let contents = b"the quick brown fox jumps over the lazy dog. ".repeat(100);
+
+        // order 6 with a 16 MiB model, restarting it when full
+        let parameters: u16 = 5 | (15 << 4);
+        let mut compressed = parameters.to_le_bytes().to_vec();
+
+        let mut encoder = ppmd_rust::Ppmd8Encoder::new(
+            &mut compressed,
+            6,
+            16 * MB as u32,
+            ppmd_rust::RestoreMethod::Restart,
+        )
+        .unwrap();
+        encoder.write_all(&contents).unwrap();
+        encoder.finish(false).unwrap();
+
+        let metadata = Metadata {
+            version_needed: 63,
+            compression_method: CompressionMethod(98),
+            date_time_modified: DateTimeModified::from_u32(0),
+            flags: ZipFlags(0),
+            name: b"fox.txt",
+            extra_field: &[],
+            compressed_size: compressed.len() as u64,
+            uncompressed_size: contents.len() as u64,
+            crc: crc32fast::hash(&contents),
+        };
+
+        let mut decompressed = Vec::new();
+        decoder(&metadata, compressed.as_slice(), &DecoderOptions::default())
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, contents);
+
+        let options = DecoderOptions {
+            ppmd_memory_limit: 8 * MB,
+            ..DecoderOptions::default()
+        };
+        assert!(matches!(
+            decoder(&metadata, compressed.as_slice(), &options),
+            Err(ZipParseError::MemoryLimitExceeded {
+                required,
+                limit,
+            }) if required == 16 * MB && limit == 8 * MB
+        ));
+
+        // the largest model possible is over the default limit
+        let mut largest = compressed.clone();
+        largest[0] |= 0xf0;
+        largest[1] |= 0x0f;
+        assert!(matches!(
+            decoder(&metadata, largest.as_slice(), &DecoderOptions::default()),
+            Err(ZipParseError::MemoryLimitExceeded { required, .. }) if required == 256 * MB
+        ));
+
+        // restoration methods other than restart and cut off are unsupported
+        compressed[1] |= 0x20;
+        assert!(decoder(&metadata, compressed.as_slice(), &DecoderOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+//! A decoder for Deflate64, also known as Enhanced Deflate.
+//!
+//! Deflate64 is Deflate with a 64 KiB window. Length code 285 takes 16 extra
+//! bits rather than always meaning 258, and distance codes 30 and 31 reach
+//! back up to 65536 bytes.
+
+use std::io::{self, BufRead, Read};
+
+use crate::{
+    bits::{invalid_data, BitReader},
+    huffman::Huffman,
+};
+
+const WINDOW_SIZE: usize = 1 << 16;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+
+const END_OF_BLOCK: u16 = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 3,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 16,
+];
+const DISTANCE_BASE: [u32; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577, 32769, 49153,
+];
+const DISTANCE_EXTRA: [u8; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13, 14, 14,
+];
+
+/// Order in which code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug)]
+enum State {
+    /// Expecting the header of the next block
+    Header,
+    /// Inside a stored block with this many bytes remaining
+    Stored(u16),
+    /// Inside a Huffman compressed block
+    Compressed {
+        lengths: Huffman,
+        distances: Huffman,
+    },
+    Done,
+}
+
+/// Streaming Deflate64 decoder. Output is produced as it is read, with only
+/// the 64 KiB window held in memory.
+#[derive(Debug)]
+pub(crate) struct Deflate64Decoder<R> {
+    bits: BitReader<R>,
+    window: Vec<u8>,
+    /// Total number of bytes produced
+    written: u64,
+    state: State,
+    is_final: bool,
+    /// A back reference which has not been fully copied out yet
+    copy_len: usize,
+    copy_distance: usize,
+}
+
+impl<R: BufRead> Deflate64Decoder<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            bits: BitReader::new(reader),
+            window: vec![0; WINDOW_SIZE],
+            written: 0,
+            state: State::Header,
+            is_final: false,
+            copy_len: 0,
+            copy_distance: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<State> {
+        if self.is_final {
+            return Ok(State::Done);
+        }
+
+        self.is_final = self.bits.bit()?;
+
+        match self.bits.bits(2)? {
+            0 => {
+                self.bits.align_to_byte();
+
+                let len = self.bits.bits(16)? as u16;
+                let nlen = self.bits.bits(16)? as u16;
+
+                if len != !nlen {
+                    return Err(invalid_data("stored block length mismatch"));
+                }
+
+                Ok(State::Stored(len))
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+
+                Ok(State::Compressed {
+                    lengths: Huffman::new(&lengths)?,
+                    distances: Huffman::new(&[5; 32])?,
+                })
+            }
+            2 => self.read_dynamic_header(),
+            _ => Err(invalid_data("invalid block type")),
+        }
+    }
+
+    fn read_dynamic_header(&mut self) -> io::Result<State> {
+        let literal_count = self.bits.bits(5)? as usize + 257;
+        let distance_count = self.bits.bits(5)? as usize + 1;
+        let code_length_count = self.bits.bits(4)? as usize + 4;
+
+        if literal_count > 286 {
+            return Err(invalid_data("too many length codes"));
+        }
+
+        let mut code_lengths = [0; 19];
+        for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_lengths[index] = self.bits.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0; literal_count + distance_count];
+        let mut index = 0;
+
+        while index < lengths.len() {
+            let symbol = code_lengths.decode(&mut self.bits)?;
+
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => match index.checked_sub(1) {
+                    Some(previous) => (lengths[previous], 3 + self.bits.bits(2)? as usize),
+                    None => return Err(invalid_data("repeat with no previous length")),
+                },
+                17 => (0, 3 + self.bits.bits(3)? as usize),
+                _ => (0, 11 + self.bits.bits(7)? as usize),
+            };
+
+            if index + repeat > lengths.len() {
+                return Err(invalid_data("too many code lengths"));
+            }
+
+            lengths[index..index + repeat].fill(value);
+            index += repeat;
+        }
+
+        if lengths[usize::from(END_OF_BLOCK)] == 0 {
+            return Err(invalid_data("missing end of block code"));
+        }
+
+        Ok(State::Compressed {
+            lengths: Huffman::new(&lengths[..literal_count])?,
+            distances: Huffman::new(&lengths[literal_count..])?,
+        })
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.window[self.written as usize & WINDOW_MASK] = byte;
+        self.written += 1;
+    }
+}
+
+impl<R: BufRead> Read for Deflate64Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        while n < buf.len() {
+            if self.copy_len > 0 {
+                let byte = self.window
+                    [(self.written as usize).wrapping_sub(self.copy_distance) & WINDOW_MASK];
+                self.push(byte);
+                buf[n] = byte;
+                n += 1;
+                self.copy_len -= 1;
+                continue;
+            }
+
+            match &mut self.state {
+                State::Header => self.state = self.read_header()?,
+                State::Stored(0) => self.state = State::Header,
+                State::Stored(remaining) => {
+                    *remaining -= 1;
+
+                    let byte = self.bits.bits(8)? as u8;
+                    self.push(byte);
+                    buf[n] = byte;
+                    n += 1;
+                }
+                State::Compressed { lengths, distances } => {
+                    let symbol = lengths.decode(&mut self.bits)?;
+
+                    if symbol < END_OF_BLOCK {
+                        let byte = symbol as u8;
+                        self.push(byte);
+                        buf[n] = byte;
+                        n += 1;
+                        continue;
+                    }
+
+                    if symbol == END_OF_BLOCK {
+                        self.state = State::Header;
+                        continue;
+                    }
+
+                    let symbol = usize::from(symbol - 257);
+                    if symbol >= LENGTH_BASE.len() {
+                        return Err(invalid_data("invalid length code"));
+                    }
+                    let len = usize::from(LENGTH_BASE[symbol])
+                        + self.bits.bits(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+
+                    let symbol = usize::from(distances.decode(&mut self.bits)?);
+                    let distance = DISTANCE_BASE[symbol] as usize
+                        + self.bits.bits(u32::from(DISTANCE_EXTRA[symbol]))? as usize;
+
+                    if distance as u64 > self.written {
+                        return Err(invalid_data("distance too far back"));
+                    }
+
+                    self.copy_len = len;
+                    self.copy_distance = distance;
+                }
+                State::Done => break,
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read, Write};
+
+    use flate2::{write::DeflateEncoder, Compression};
+
+    use crate::{bits::BitWriter, CompressionMethod, FileOptions, ZipArchive, ZipWriter};
+
+    use super::Deflate64Decoder;
+
+    /// A literal/length symbol of the fixed Huffman code
+    fn fixed(writer: &mut BitWriter, symbol: u32) {
+        match symbol {
+            0..=143 => writer.code(0b0011_0000 + symbol, 8),
+            144..=255 => writer.code(0b1_1001_0000 + symbol - 144, 9),
+            256..=279 => writer.code(symbol - 256, 7),
+            _ => writer.code(0b1100_0000 + symbol - 280, 8),
+        }
+    }
+
+    #[test]
+    fn plain_deflate() {
+        // without length code 285 or distances past 32 KiB, deflate and
+        // deflate64 are the same
+        let mut state = 1u32;
+        let contents = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                b"abcdefgh"[state as usize % 8]
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&contents).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoded = Vec::new();
+        Deflate64Decoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+
+        assert_eq!(decoded, contents);
+    }
+
+    #[test]
+    fn extended_codes() {
+        let mut expected = (0..60_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        let mut w = BitWriter::default();
+        w.bits(1, 1);
+        w.bits(1, 2);
+
+        for &byte in &expected {
+            fixed(&mut w, u32::from(byte));
+        }
+
+        // length code 285 with 16 extra bits, and distance code 31 with 14
+        fixed(&mut w, 285);
+        w.bits(1000, 16);
+        w.code(31, 5);
+        w.bits(100, 14);
+
+        let distance = 49153 + 100;
+        for _ in 0..1003 {
+            expected.push(expected[expected.len() - distance]);
+        }
+
+        // distance code 30
+        fixed(&mut w, 284);
+        w.bits(0, 5);
+        w.code(30, 5);
+        w.bits(0, 14);
+
+        for _ in 0..227 {
+            expected.push(expected[expected.len() - 32769]);
+        }
+
+        fixed(&mut w, 256);
+
+        let mut decoder = Deflate64Decoder::new(w.bytes.as_slice());
+        let mut decoded = Vec::new();
+
+        // small reads exercise resuming in the middle of a back reference
+        let mut buf = [0; 7];
+        loop {
+            let n = decoder.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..n]);
+        }
+
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn archive() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.start_file("a.txt", FileOptions::default()).unwrap();
+        writer.write_all(b"enhanced deflate").unwrap();
+
+        let mut buffer = writer.finish().unwrap().into_inner();
+
+        // relabel the deflate contents as deflate64
+        let central =
+            memchr::memmem::rfind(&buffer, &crate::CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+        buffer[8] = 9;
+        buffer[central + 10] = 9;
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let file = archive.files().next().unwrap().unwrap();
+
+        assert_eq!(file.compression_method(), CompressionMethod(9));
+        assert_eq!(
+            file.decompressed_contents().unwrap(),
+            &b"enhanced deflate"[..]
+        );
+    }
+}
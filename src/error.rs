@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::CompressionMethod;
+
 #[derive(Error, Debug)]
 pub enum ZipParseError {
     #[error("file too big. was {0} bytes")]
@@ -14,4 +16,28 @@ pub enum ZipParseError {
     UnexpectedEof,
     #[error("unable to locate central directory signature")]
     MissingCentralDirectory,
+    #[error("malformed extra field with header id {0:#06x}")]
+    MalformedExtraField(u16),
+    #[error("crc-32 mismatch. expected {expected:#010x}, found {found:#010x}")]
+    CrcMismatch { expected: u32, found: u32 },
+    #[error("refusing to extract unsafe path {0:?}")]
+    UnsafePath(String),
+    #[error("unsupported compression method {} ({:?})", .0 .0, .0.name())]
+    UnsupportedCompression(CompressionMethod),
+    #[error("decoder requires {required} bytes of memory, over the limit of {limit}")]
+    MemoryLimitExceeded { required: usize, limit: usize },
+    #[error("file is encrypted, but no password was given")]
+    PasswordRequired,
+    #[error("invalid password")]
+    InvalidPassword,
+    #[error("no file named {0:?}")]
+    FileNotFound(String),
+    #[error("file compressed more than the limit of {0} to 1")]
+    RatioLimitExceeded(u32),
+    #[error("archive has {found} files, over the limit of {limit}")]
+    TooManyFiles { found: usize, limit: usize },
+    #[error("file name of {found} bytes, over the limit of {limit}")]
+    NameTooLong { found: usize, limit: usize },
+    #[error("files overlap at offset {0:#x}")]
+    OverlappingFiles(u64),
 }
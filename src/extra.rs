@@ -0,0 +1,465 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{common::*, parse::Parser, ZipParseError};
+
+/// Seconds between 1601-01-01, the NTFS epoch, and 1970-01-01
+const NTFS_EPOCH_OFFSET_SECS: u64 = 11_644_473_600;
+
+/// A single record of the extra field, parsed according to its header ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtraField<'a> {
+    /// Zip64 extended information (0x0001)
+    Zip64(Zip64ExtraField<'a>),
+    /// NTFS timestamps (0x000a)
+    Ntfs(Timestamps),
+    /// Extended timestamp (0x5455)
+    ExtendedTimestamp(Timestamps),
+    /// Info-ZIP Unix UID/GID (0x7875)
+    UnixOwner { uid: u64, gid: u64 },
+    /// Info-ZIP Unicode path (0x7075)
+    UnicodePath(UnicodeExtraField<'a>),
+    /// Info-ZIP Unicode comment (0x6375)
+    UnicodeComment(UnicodeExtraField<'a>),
+    /// WinZip AES encryption (0x9901)
+    WinZipAes(WinZipAesExtraField),
+    /// Any other header ID, left unparsed
+    Unknown { id: u16, data: &'a [u8] },
+}
+
+impl<'a> ExtraField<'a> {
+    /// Parse the data of a single extra field record
+    pub fn parse(id: u16, data: &'a [u8]) -> Result<ExtraField<'a>, ZipParseError> {
+        let malformed = |_| ZipParseError::MalformedExtraField(id);
+
+        match id {
+            ZIP64_EXTRA_FIELD_ID => Zip64ExtraField::new(data).map(ExtraField::Zip64),
+            NTFS_EXTRA_FIELD_ID => parse_ntfs(data).map(ExtraField::Ntfs).map_err(malformed),
+            EXTENDED_TIMESTAMP_EXTRA_FIELD_ID => parse_extended_timestamp(data)
+                .map(ExtraField::ExtendedTimestamp)
+                .map_err(malformed),
+            UNIX_OWNER_EXTRA_FIELD_ID => parse_unix_owner(data)
+                .map(|(uid, gid)| ExtraField::UnixOwner { uid, gid })
+                .map_err(malformed),
+            UNICODE_PATH_EXTRA_FIELD_ID => UnicodeExtraField::parse(data)
+                .map(ExtraField::UnicodePath)
+                .map_err(malformed),
+            UNICODE_COMMENT_EXTRA_FIELD_ID => UnicodeExtraField::parse(data)
+                .map(ExtraField::UnicodeComment)
+                .map_err(malformed),
+            WINZIP_AES_EXTRA_FIELD_ID => WinZipAesExtraField::parse(data)
+                .map(ExtraField::WinZipAes)
+                .map_err(malformed),
+            id => Ok(ExtraField::Unknown { id, data }),
+        }
+    }
+}
+
+/// Iterator over the records of an extra field
+///
+/// Iteration stops after the first error.
+#[derive(Debug, Clone)]
+pub struct ExtraFields<'a> {
+    records: ExtraFieldRecords<'a>,
+}
+
+impl<'a> ExtraFields<'a> {
+    pub fn new(extra_field: &'a [u8]) -> Self {
+        Self {
+            records: ExtraFieldRecords::new(extra_field),
+        }
+    }
+}
+
+impl<'a> Iterator for ExtraFields<'a> {
+    type Item = Result<ExtraField<'a>, ZipParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, data) = match self.records.next()? {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let field = ExtraField::parse(id, data);
+
+        if field.is_err() {
+            self.records.done = true;
+        }
+
+        Some(field)
+    }
+}
+
+/// Iterator over the raw `(header ID, data)` records of an extra field
+#[derive(Debug, Clone)]
+pub(crate) struct ExtraFieldRecords<'a> {
+    data: &'a [u8],
+    done: bool,
+}
+
+impl<'a> ExtraFieldRecords<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, done: false }
+    }
+}
+
+impl<'a> Iterator for ExtraFieldRecords<'a> {
+    type Item = Result<(u16, &'a [u8]), ZipParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.data.is_empty() {
+            return None;
+        }
+
+        // some writers pad the extra field with a few zero bytes, which are
+        // too short to be a record
+        if self.data.len() < 4 {
+            self.done = true;
+
+            return if self.data.iter().all(|&b| b == 0) {
+                None
+            } else {
+                Some(Err(ZipParseError::UnexpectedEof))
+            };
+        }
+
+        let id = u16::from_le_bytes([self.data[0], self.data[1]]);
+        let len = usize::from(u16::from_le_bytes([self.data[2], self.data[3]]));
+
+        match self.data.get(4..4 + len) {
+            Some(data) => {
+                self.data = &self.data[4 + len..];
+                Some(Ok((id, data)))
+            }
+            None => {
+                self.done = true;
+                Some(Err(ZipParseError::MalformedExtraField(id)))
+            }
+        }
+    }
+}
+
+/// Find the data of the first extra field record with the given header ID,
+/// ignoring any malformed records
+pub(crate) fn find_extra_field(extra_field: &[u8], id: u16) -> Option<&[u8]> {
+    ExtraFieldRecords::new(extra_field)
+        .map_while(Result::ok)
+        .find(|&(header_id, _)| header_id == id)
+        .map(|(_, data)| data)
+}
+
+/// The zip64 extended information extra field.
+///
+/// This holds the values of any header fields which were saturated to
+/// `0xFFFFFFFF` (or `0xFFFF` for the disk number), in a fixed order, so which
+/// value is which can only be determined alongside the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Zip64ExtraField<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Zip64ExtraField<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, ZipParseError> {
+        // up to three 8 byte values, optionally followed by a 4 byte disk number
+        if data.len() > 28 || !matches!(data.len() % 8, 0 | 4) {
+            return Err(ZipParseError::MalformedExtraField(ZIP64_EXTRA_FIELD_ID));
+        }
+
+        Ok(Self { data })
+    }
+
+    /// Replace each saturated value with its real value.
+    ///
+    /// `local_header_offset` and `disk_num_start` are only present in the
+    /// central directory.
+    pub fn resolve(
+        &self,
+        uncompressed_size: &mut u64,
+        compressed_size: &mut u64,
+        local_header_offset: Option<&mut u64>,
+        disk_num_start: Option<&mut u32>,
+    ) -> Result<(), ZipParseError> {
+        let mut parser = Parser::new(self.data);
+        let malformed = |_| ZipParseError::MalformedExtraField(ZIP64_EXTRA_FIELD_ID);
+
+        if *uncompressed_size == u64::from(u32::MAX) {
+            *uncompressed_size = parser.read_u64().map_err(malformed)?;
+        }
+
+        if *compressed_size == u64::from(u32::MAX) {
+            *compressed_size = parser.read_u64().map_err(malformed)?;
+        }
+
+        if let Some(offset) = local_header_offset {
+            if *offset == u64::from(u32::MAX) {
+                *offset = parser.read_u64().map_err(malformed)?;
+            }
+        }
+
+        if let Some(disk) = disk_num_start {
+            if *disk == u32::from(u16::MAX) {
+                *disk = parser.read_u32().map_err(malformed)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Timestamps with a higher resolution or range than the MS-DOS date and time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamps {
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+}
+
+impl Timestamps {
+    /// Find the timestamps in the NTFS extra field, or failing that, the
+    /// extended timestamp extra field
+    pub(crate) fn from_extra_field(extra_field: &[u8]) -> Timestamps {
+        if let Some(timestamps) = find_extra_field(extra_field, NTFS_EXTRA_FIELD_ID)
+            .and_then(|data| parse_ntfs(data).ok())
+        {
+            return timestamps;
+        }
+
+        find_extra_field(extra_field, EXTENDED_TIMESTAMP_EXTRA_FIELD_ID)
+            .and_then(|data| parse_extended_timestamp(data).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// The NTFS extra field is a sequence of attributes following 4 reserved
+/// bytes. Attribute 1 holds the modification, access and creation times as
+/// 100ns intervals since 1601.
+fn parse_ntfs(data: &[u8]) -> Result<Timestamps, ZipParseError> {
+    let mut parser = Parser::new(data);
+
+    let _reserved = parser.read_u32()?;
+
+    loop {
+        let tag = parser.read_u16()?;
+        let len = usize::from(parser.read_u16()?);
+        let attribute = parser.get_byte_range(len)?;
+
+        if tag != 1 || len < 24 {
+            continue;
+        }
+
+        let mut attribute = Parser::new(attribute);
+        let mut time = || -> Result<Option<SystemTime>, ZipParseError> {
+            let ticks = attribute.read_u64()?;
+            let since_ntfs_epoch =
+                Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100);
+
+            Ok(since_ntfs_epoch
+                .checked_sub(Duration::from_secs(NTFS_EPOCH_OFFSET_SECS))
+                .map(|since_unix_epoch| UNIX_EPOCH + since_unix_epoch))
+        };
+
+        return Ok(Timestamps {
+            modified: time()?,
+            accessed: time()?,
+            created: time()?,
+        });
+    }
+}
+
+/// The extended timestamp extra field is a flags byte followed by signed
+/// 32-bit Unix timestamps for each flag set. In the central directory, only the
+/// modification time is stored, even if other flags are set.
+fn parse_extended_timestamp(data: &[u8]) -> Result<Timestamps, ZipParseError> {
+    let mut parser = Parser::new(data);
+
+    let flags = parser.read_u8()?;
+
+    let mut time = |bit: u8| {
+        if flags & bit == 0 {
+            return None;
+        }
+
+        let secs = parser.read_u32().ok()? as i32;
+
+        Some(if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(u64::from(secs.unsigned_abs()))
+        })
+    };
+
+    Ok(Timestamps {
+        modified: time(1 << 0),
+        accessed: time(1 << 1),
+        created: time(1 << 2),
+    })
+}
+
+/// A version byte, followed by the size and value of the UID, then the size and
+/// value of the GID
+fn parse_unix_owner(data: &[u8]) -> Result<(u64, u64), ZipParseError> {
+    let mut parser = Parser::new(data);
+
+    let _version = parser.read_u8()?;
+
+    let mut id = || -> Result<u64, ZipParseError> {
+        let len = usize::from(parser.read_u8()?);
+        let bytes = parser.get_byte_range(len)?;
+
+        // ids wider than 64 bits are only valid if the excess is zero
+        if bytes.iter().skip(8).any(|&b| b != 0) {
+            return Err(ZipParseError::Generic("unix id too large"));
+        }
+
+        Ok(bytes
+            .iter()
+            .take(8)
+            .rev()
+            .fold(0, |acc, &b| (acc << 8) | u64::from(b)))
+    };
+
+    let uid = id()?;
+    let gid = id()?;
+
+    Ok((uid, gid))
+}
+
+/// The Info-ZIP Unicode path and comment extra fields, which hold a UTF-8
+/// version of a field stored in the header with some other encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnicodeExtraField<'a> {
+    pub version: u8,
+    /// The CRC-32 of the field in the header this replaces. If it doesn't
+    /// match, the header was changed by a tool unaware of this extra field and
+    /// this value is stale.
+    pub crc: u32,
+    /// UTF-8 encoded value, which is not guaranteed to be valid
+    pub value: &'a [u8],
+}
+
+impl<'a> UnicodeExtraField<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, ZipParseError> {
+        let mut parser = Parser::new(data);
+
+        let version = parser.read_u8()?;
+        let crc = parser.read_u32()?;
+        let value = &data[5..];
+
+        Ok(Self {
+            version,
+            crc,
+            value,
+        })
+    }
+
+    /// Whether this value replaces `original`, according to the stored crc
+    pub fn matches(&self, original: &[u8]) -> bool {
+        self.version == 1 && crc32fast::hash(original) == self.crc
+    }
+}
+
+/// The WinZip AES extra field, present on files encrypted with AES. The real
+/// compression method is stored here, as the header's is set to 99.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinZipAesExtraField {
+    /// 1 for AE-1, 2 for AE-2
+    pub vendor_version: u16,
+    /// Always `b"AE"`
+    pub vendor_id: [u8; 2],
+    /// 1, 2 or 3 for AES-128, AES-192 or AES-256 respectively
+    pub strength: u8,
+    pub compression_method: CompressionMethod,
+}
+
+impl WinZipAesExtraField {
+    pub(crate) fn parse(data: &[u8]) -> Result<Self, ZipParseError> {
+        if data.len() != 7 {
+            return Err(ZipParseError::UnexpectedEof);
+        }
+
+        let mut parser = Parser::new(data);
+
+        Ok(Self {
+            vendor_version: parser.read_u16()?,
+            vendor_id: [parser.read_u8()?, parser.read_u8()?],
+            strength: parser.read_u8()?,
+            compression_method: CompressionMethod(parser.read_u16()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ExtraField, ExtraFields, ZipParseError};
+
+    #[test]
+    fn typed_records() {
+        let mut data = Vec::new();
+
+        // unix owner, with a 4 byte uid and 2 byte gid
+        data.extend_from_slice(&[0x75, 0x78, 9, 0, 1, 4, 0xe8, 0x03, 0, 0, 2, 0x64, 0]);
+        // unicode path
+        data.extend_from_slice(&[0x75, 0x70, 7, 0, 1]);
+        data.extend_from_slice(&crc32fast::hash(b"a").to_le_bytes());
+        data.extend_from_slice("é".as_bytes());
+        // unknown
+        data.extend_from_slice(&[0xfe, 0xca, 1, 0, 42]);
+
+        let fields = ExtraFields::new(&data)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            fields[0],
+            ExtraField::UnixOwner {
+                uid: 1000,
+                gid: 100
+            }
+        );
+
+        match &fields[1] {
+            ExtraField::UnicodePath(path) => {
+                assert_eq!(path.value, "é".as_bytes());
+                assert!(path.matches(b"a"));
+                assert!(!path.matches(b"b"));
+            }
+            field => panic!("unexpected {:?}", field),
+        }
+
+        assert_eq!(
+            fields[2],
+            ExtraField::Unknown {
+                id: 0xcafe,
+                data: &[42]
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_lengths() {
+        // claims 16 bytes but only has 2
+        let truncated = [0xfe, 0xca, 16, 0, 1, 2];
+        let mut fields = ExtraFields::new(&truncated);
+
+        assert!(matches!(
+            fields.next(),
+            Some(Err(ZipParseError::MalformedExtraField(0xcafe)))
+        ));
+        assert!(fields.next().is_none());
+
+        // a zip64 field must hold whole values
+        let zip64 = [0x01, 0x00, 3, 0, 1, 2, 3];
+
+        assert!(matches!(
+            ExtraFields::new(&zip64).next(),
+            Some(Err(ZipParseError::MalformedExtraField(0x0001)))
+        ));
+
+        // aes fields are a fixed size
+        let aes = [0x01, 0x99, 2, 0, 1, 2];
+
+        assert!(matches!(
+            ExtraFields::new(&aes).next(),
+            Some(Err(ZipParseError::MalformedExtraField(0x9901)))
+        ));
+    }
+}
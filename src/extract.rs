@@ -0,0 +1,1217 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    decompress::Crc32Reader, parse::range_within, CentralDirectoryFileHeader, CompressedZipFile,
+    ExternalAttributes, ZipArchive, ZipParseError, DEFAULT_PPMD_MEMORY_LIMIT, GB,
+};
+
+/// What to do when a file being extracted already exists on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overwrite {
+    /// Fail with [`std::io::ErrorKind::AlreadyExists`]
+    #[default]
+    Error,
+    /// Leave the existing file untouched and continue with the next entry
+    Skip,
+    /// Truncate the existing file and replace its contents
+    Replace,
+}
+
+/// Options for [`ZipArchive::extract_to`]
+///
+/// ```no_run
+/// # use zip_rs::{ExtractOptions, Overwrite, ZipArchive};
+/// let archive = ZipArchive::from_path("./foo.zip").unwrap();
+///
+/// archive
+///     .extract_to(
+///         "./foo",
+///         ExtractOptions::default()
+///             .overwrite(Overwrite::Replace)
+///             .limit(Some(1024 * 1024 * 1024)),
+///     )
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    overwrite: Overwrite,
+    limits: ExtractionLimits,
+    permissions: bool,
+    symlinks: bool,
+    modified_times: bool,
+    ppmd_memory_limit: usize,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            overwrite: Overwrite::default(),
+            limits: ExtractionLimits::default(),
+            permissions: false,
+            symlinks: false,
+            modified_times: false,
+            ppmd_memory_limit: DEFAULT_PPMD_MEMORY_LIMIT,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// What to do when a file already exists. Defaults to
+    /// [`Overwrite::Error`].
+    pub fn overwrite(mut self, overwrite: Overwrite) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// The maximum number of decompressed bytes written across all files. A
+    /// `limit` of `None` implies no limit. Defaults to 8 gigabytes.
+    ///
+    /// This is a shorthand for [`ExtractionLimits::total_size`].
+    pub fn limit(mut self, limit: Option<usize>) -> Self {
+        self.limits.total_size = limit.map(|limit| limit as u64);
+        self
+    }
+
+    /// Limits on what extracting the archive may produce. See
+    /// [`ExtractionLimits`].
+    pub fn limits(mut self, limits: ExtractionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Restore the permission bits of files and directories created on Unix.
+    /// The setuid, setgid and sticky bits are never restored. Defaults to
+    /// `false`, and has no effect on other platforms.
+    pub fn permissions(mut self, permissions: bool) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Recreate symbolic links created on Unix rather than writing their
+    /// target to a regular file. Defaults to `false`, and has no effect on
+    /// other platforms.
+    ///
+    /// Links which are absolute, or which could point outside of the directory
+    /// being extracted to, are rejected with [`ZipParseError::UnsafePath`].
+    pub fn symlinks(mut self, symlinks: bool) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Set the modification time of extracted files and directories, taken
+    /// from [`crate::Metadata::modified`]. Defaults to `false`.
+    pub fn modified_times(mut self, modified_times: bool) -> Self {
+        self.modified_times = modified_times;
+        self
+    }
+
+    /// The most memory, in bytes, a PPMd model may use. See
+    /// [`CompressedZipFile::ppmd_memory_limit`].
+    pub fn ppmd_memory_limit(mut self, limit: usize) -> Self {
+        self.ppmd_memory_limit = limit;
+        self
+    }
+}
+
+/// Limits on what extracting an archive may produce, as a defense against zip
+/// bombs. See [`ExtractOptions::limits`].
+///
+/// The total size and compression ratio are enforced on the bytes actually
+/// produced by the decoders as they are written, not on the sizes the archive
+/// claims. The rest are checked before anything is extracted, by
+/// [`ZipArchive::check_limits`].
+///
+/// ```no_run
+/// # use zip_rs::{ExtractOptions, ExtractionLimits, ZipArchive};
+/// let archive = ZipArchive::from_path("./foo.zip").unwrap();
+///
+/// let limits = ExtractionLimits::default()
+///     .total_size(Some(1024 * 1024 * 1024))
+///     .max_ratio(Some(100))
+///     .max_files(Some(10_000))
+///     .max_name_len(Some(255));
+///
+/// archive
+///     .extract_to("./foo", ExtractOptions::default().limits(limits))
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExtractionLimits {
+    total_size: Option<u64>,
+    max_ratio: Option<u32>,
+    max_files: Option<usize>,
+    max_name_len: Option<usize>,
+}
+
+impl Default for ExtractionLimits {
+    fn default() -> Self {
+        Self {
+            total_size: Some(8 * GB as u64),
+            max_ratio: None,
+            max_files: None,
+            max_name_len: None,
+        }
+    }
+}
+
+impl ExtractionLimits {
+    /// The maximum number of decompressed bytes written across all files,
+    /// failing with [`ZipParseError::FileTooLarge`] once more are produced.
+    /// Defaults to 8 gigabytes.
+    pub fn total_size(mut self, limit: Option<u64>) -> Self {
+        self.total_size = limit;
+        self
+    }
+
+    /// The maximum number of decompressed bytes a file may produce per byte of
+    /// its compressed contents, failing with
+    /// [`ZipParseError::RatioLimitExceeded`] once it produces more. Defaults to
+    /// no limit.
+    ///
+    /// Deflate can't compress better than about 1032 to 1, but other methods
+    /// can, and files of repeated bytes legitimately come close to that.
+    pub fn max_ratio(mut self, limit: Option<u32>) -> Self {
+        self.max_ratio = limit;
+        self
+    }
+
+    /// The maximum number of files in the archive, counting directories,
+    /// otherwise failing with [`ZipParseError::TooManyFiles`]. Defaults to no
+    /// limit.
+    pub fn max_files(mut self, limit: Option<usize>) -> Self {
+        self.max_files = limit;
+        self
+    }
+
+    /// The maximum length in bytes of the name of any file in the archive,
+    /// otherwise failing with [`ZipParseError::NameTooLong`]. Defaults to no
+    /// limit.
+    pub fn max_name_len(mut self, limit: Option<usize>) -> Self {
+        self.max_name_len = limit;
+        self
+    }
+}
+
+impl<B: std::ops::Deref<Target = [u8]>> ZipArchive<B> {
+    /// Check the parts of `limits` that can be checked without decompressing
+    /// anything, as [`ZipArchive::extract_to`] does before extracting.
+    ///
+    /// This also fails with [`ZipParseError::OverlappingFiles`] if the local
+    /// header and contents of any two files overlap, which no archive needs
+    /// to do. "Non-recursive" zip bombs overlap files to have the same
+    /// compressed bytes decompressed over and over again.
+    pub fn check_limits(&self, limits: &ExtractionLimits) -> Result<(), ZipParseError> {
+        if let Some(limit) = limits.max_files.filter(|&limit| self.len() > limit) {
+            return Err(ZipParseError::TooManyFiles {
+                found: self.len(),
+                limit,
+            });
+        }
+
+        let mut ranges = Vec::with_capacity(self.len());
+
+        for (index, header) in self.central_directory().files.iter().enumerate() {
+            let name_len = header.metadata.name.len();
+
+            if let Some(limit) = limits.max_name_len.filter(|&limit| name_len > limit) {
+                return Err(ZipParseError::NameTooLong {
+                    found: name_len,
+                    limit,
+                });
+            }
+
+            let contents = range_within(&self.buffer, self.by_index(index)?.contents);
+
+            ranges.push((header.local_header_offset, contents.end as u64));
+        }
+
+        ranges.sort_unstable();
+
+        match ranges.windows(2).find(|pair| pair[1].0 < pair[0].1) {
+            Some(pair) => Err(ZipParseError::OverlappingFiles(pair[1].0)),
+            None => Ok(()),
+        }
+    }
+
+    /// Extract every file in this archive into the directory `dir`, creating it
+    /// if it does not exist.
+    ///
+    /// Entry names are sanitized before touching the file system. Leading `/`
+    /// and Windows drive prefixes are stripped, and `\` is treated as a path
+    /// separator. Names containing a parent directory component (`..`) are
+    /// rejected with [`ZipParseError::UnsafePath`], as is any attempt to write
+    /// through a symbolic link that already exists inside `dir`.
+    pub fn extract_to(
+        &self,
+        dir: impl AsRef<Path>,
+        options: ExtractOptions,
+    ) -> Result<(), ZipParseError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        self.check_limits(&options.limits)?;
+
+        let mut budget = Budget::new(&options.limits);
+
+        let central_directory = self.central_directory();
+        let mut directories = Vec::new();
+
+        for (index, header) in central_directory.files.iter().enumerate() {
+            let file = self
+                .by_index(index)?
+                .ppmd_memory_limit(options.ppmd_memory_limit);
+
+            match prepare_file(header, &file, dir, &options, &mut budget)? {
+                Prepared::Directory(path) => directories.push((path, header)),
+                Prepared::Done => {}
+                Prepared::File { path, out } => {
+                    write_file(header, &file, &path, out, &options, &mut budget)?;
+                }
+            }
+        }
+
+        finish_directories(&directories, &options)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<B: std::ops::Deref<Target = [u8]>> ZipArchive<B> {
+    /// Extract every file in this archive into the directory `dir`, like
+    /// [`ZipArchive::extract_to`], but writing the contents of files in
+    /// parallel on the rayon thread pool.
+    ///
+    /// Every directory and file is first created in the order of the archive,
+    /// exactly as [`ZipArchive::extract_to`] would, and only then are the
+    /// files decompressed. So that the outcome doesn't depend on the order in
+    /// which they finish, each file is set aside its declared uncompressed size
+    /// of [`ExtractionLimits::total_size`] up front, and fails if it produces
+    /// more than that.
+    ///
+    /// If several files fail, the error is that of the first of them in the
+    /// archive. Files after it may or may not have been extracted.
+    pub fn par_extract_to(
+        &self,
+        dir: impl AsRef<Path>,
+        options: ExtractOptions,
+    ) -> Result<(), ZipParseError> {
+        use rayon::prelude::*;
+        use std::{
+            collections::HashMap,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        self.check_limits(&options.limits)?;
+
+        let mut budget = Budget::new(&options.limits);
+
+        let central_directory = self.central_directory();
+        let mut directories = Vec::new();
+        let mut jobs = Vec::new();
+        let mut job_paths = HashMap::new();
+
+        // failing to create a file is only reported if none of the files
+        // before it fail to be written
+        let mut prepare_error = None;
+
+        for (index, header) in central_directory.files.iter().enumerate() {
+            let result = self.by_index(index).and_then(|file| {
+                let file = file.ppmd_memory_limit(options.ppmd_memory_limit);
+
+                match prepare_file(header, &file, dir, &options, &mut budget)? {
+                    Prepared::Directory(path) => directories.push((path, header)),
+                    Prepared::Done => {}
+                    Prepared::File { path, out } => {
+                        let job = Job::new(header, file, path, &out, &mut budget)?;
+
+                        // a later file with the same path can only get this
+                        // far with `Overwrite::Replace`, and replaces the
+                        // earlier one just as it would when extracting in order
+                        if let Some(earlier) = job_paths.insert(job.path.clone(), jobs.len()) {
+                            jobs[earlier] = None;
+                        }
+
+                        jobs.push(Some(job));
+                    }
+                }
+
+                Ok(())
+            });
+
+            if let Err(err) = result {
+                prepare_error = Some(err);
+                break;
+            }
+        }
+
+        let jobs = jobs.into_iter().flatten().collect::<Vec<_>>();
+        let first_failure = AtomicUsize::new(usize::MAX);
+
+        let results = jobs
+            .par_iter()
+            .enumerate()
+            .map(|(i, job)| {
+                // the error of an earlier file is returned instead
+                if first_failure.load(Ordering::Relaxed) < i {
+                    let _ = fs::remove_file(&job.path);
+                    return Ok(());
+                }
+
+                let result = job.run(&options);
+
+                if result.is_err() {
+                    first_failure.fetch_min(i, Ordering::Relaxed);
+                }
+
+                result
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(err) = results.into_iter().find_map(Result::err).or(prepare_error) {
+            return Err(err);
+        }
+
+        finish_directories(&directories, &options)
+    }
+}
+
+/// A file which has been created, waiting to be written in parallel with the
+/// others
+#[cfg(feature = "rayon")]
+struct Job<'a> {
+    header: &'a CentralDirectoryFileHeader<'a>,
+    file: CompressedZipFile<'a>,
+    path: PathBuf,
+    /// Identifies the file created, so that it can't be swapped for another
+    /// before it is reopened
+    id: Option<(u64, u64)>,
+    /// The part of the budget set aside for this file
+    limit: Option<u64>,
+}
+
+#[cfg(feature = "rayon")]
+impl<'a> Job<'a> {
+    fn new(
+        header: &'a CentralDirectoryFileHeader<'a>,
+        file: CompressedZipFile<'a>,
+        path: PathBuf,
+        out: &File,
+        budget: &mut Budget,
+    ) -> Result<Self, ZipParseError> {
+        let reserved = file_id(out).map_err(ZipParseError::from).and_then(|id| {
+            let limit = budget.reserve(file.metadata.uncompressed_size)?;
+
+            Ok((id, limit))
+        });
+
+        match reserved {
+            Ok((id, limit)) => Ok(Job {
+                header,
+                file,
+                path,
+                id,
+                limit,
+            }),
+            Err(err) => {
+                let _ = fs::remove_file(&path);
+                Err(err)
+            }
+        }
+    }
+
+    fn run(&self, options: &ExtractOptions) -> Result<(), ZipParseError> {
+        let out = OpenOptions::new().write(true).open(&self.path)?;
+
+        if file_id(&out)? != self.id {
+            return Err(ZipParseError::UnsafePath(self.path.display().to_string()));
+        }
+
+        let mut budget = Budget {
+            written: 0,
+            limit: self.limit,
+            max_ratio: options.limits.max_ratio,
+        };
+
+        write_file(
+            self.header,
+            &self.file,
+            &self.path,
+            out,
+            options,
+            &mut budget,
+        )
+    }
+}
+
+#[cfg(all(feature = "rayon", target_family = "unix"))]
+fn file_id(file: &File) -> io::Result<Option<(u64, u64)>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata()?;
+
+    Ok(Some((metadata.dev(), metadata.ino())))
+}
+
+#[cfg(all(feature = "rayon", not(target_family = "unix")))]
+fn file_id(_file: &File) -> io::Result<Option<(u64, u64)>> {
+    Ok(None)
+}
+
+/// Directories are finished last, as adding their contents would otherwise
+/// change their modification time or be denied by their permissions. Children
+/// are finished before their parents.
+fn finish_directories(
+    directories: &[(PathBuf, &CentralDirectoryFileHeader<'_>)],
+    options: &ExtractOptions,
+) -> Result<(), ZipParseError> {
+    for (path, header) in directories.iter().rev() {
+        if options.modified_times && cfg!(target_family = "unix") {
+            if let Some(modified) = header.metadata.modified() {
+                File::open(path)?.set_modified(modified)?;
+            }
+        }
+
+        if options.permissions {
+            set_permissions(path, header)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What is left to do for a file once everything but its contents has been
+/// taken care of
+#[derive(Debug)]
+enum Prepared {
+    /// A directory, whose metadata is restored once the rest of the archive
+    /// has been extracted
+    Directory(PathBuf),
+    /// A file which was skipped, or a symbolic link, which is created right
+    /// away
+    Done,
+    /// A regular file, which has been created but not yet written to
+    File { path: PathBuf, out: File },
+}
+
+/// Create the file or directory for an entry, and any directories containing
+/// it, without writing its contents
+fn prepare_file(
+    header: &CentralDirectoryFileHeader<'_>,
+    file: &CompressedZipFile<'_>,
+    dir: &Path,
+    options: &ExtractOptions,
+    budget: &mut Budget,
+) -> Result<Prepared, ZipParseError> {
+    let name = file.file_name();
+    let relative =
+        sanitize_path(&name).ok_or_else(|| ZipParseError::UnsafePath(name.clone().into_owned()))?;
+
+    let is_dir = name.ends_with('/')
+        || name.ends_with('\\')
+        || header.unix_mode().is_some_and(|mode| {
+            mode & ExternalAttributes::UNIX_FILE_TYPE == ExternalAttributes::UNIX_DIRECTORY
+        });
+
+    if is_dir {
+        create_dir_all(dir, &relative)?;
+
+        if relative.as_os_str().is_empty() {
+            return Ok(Prepared::Done);
+        }
+
+        return Ok(Prepared::Directory(dir.join(&relative)));
+    }
+
+    if relative.as_os_str().is_empty() {
+        return Err(ZipParseError::UnsafePath(name.into_owned()));
+    }
+
+    if let Some(parent) = relative.parent() {
+        create_dir_all(dir, parent)?;
+    }
+
+    let path = dir.join(&relative);
+
+    match fs::symlink_metadata(&path) {
+        Ok(existing) if existing.file_type().is_symlink() && !options.symlinks => {
+            return Err(ZipParseError::UnsafePath(name.into_owned()));
+        }
+        Ok(existing) => match options.overwrite {
+            Overwrite::Error => {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists).into());
+            }
+            Overwrite::Skip => return Ok(Prepared::Done),
+            // links are never written through, so replacing one means
+            // removing it first
+            Overwrite::Replace if existing.file_type().is_symlink() => fs::remove_file(&path)?,
+            Overwrite::Replace => {}
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    if options.symlinks && header.is_symlink() && cfg!(target_family = "unix") {
+        let mut target = Vec::new();
+        write_contents(file, &mut target, budget)?;
+
+        return create_symlink(&relative, &target, &path, &name).map(|()| Prepared::Done);
+    }
+
+    // `create_new` closes the window between the check above and opening the
+    // file, in which a symbolic link could otherwise be planted
+    let out = match options.overwrite {
+        Overwrite::Replace => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?,
+        Overwrite::Error | Overwrite::Skip => OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?,
+    };
+
+    Ok(Prepared::File { path, out })
+}
+
+/// Write the contents of a file created by [`prepare_file`], removing it if
+/// that fails
+fn write_file(
+    header: &CentralDirectoryFileHeader<'_>,
+    file: &CompressedZipFile<'_>,
+    path: &Path,
+    mut out: File,
+    options: &ExtractOptions,
+    budget: &mut Budget,
+) -> Result<(), ZipParseError> {
+    let result = write_contents(file, &mut out, budget).and_then(|()| {
+        if options.modified_times {
+            if let Some(modified) = file.metadata.modified() {
+                out.set_modified(modified)?;
+            }
+        }
+
+        if options.permissions {
+            set_permissions(path, header)?;
+        }
+
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+
+    result
+}
+
+fn write_contents(
+    file: &CompressedZipFile<'_>,
+    out: &mut dyn Write,
+    budget: &mut Budget,
+) -> Result<(), ZipParseError> {
+    let mut decoder = Crc32Reader::new(file.decoder()?);
+
+    let max_size = budget
+        .max_ratio
+        .map(|ratio| (file.compressed_contents().len() as u64).saturating_mul(u64::from(ratio)));
+
+    let mut out = BudgetWriter {
+        inner: out,
+        budget,
+        written: 0,
+        max_size,
+        exceeded: None,
+    };
+
+    match io::copy(&mut decoder, &mut out) {
+        Ok(_) => file.check_crc(decoder.crc()),
+        Err(err) => Err(out.exceeded.take().unwrap_or_else(|| err.into())),
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn set_permissions(path: &Path, header: &CentralDirectoryFileHeader<'_>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match header.unix_mode() {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+fn set_permissions(_path: &Path, _header: &CentralDirectoryFileHeader<'_>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Create a symbolic link at `path`, which is `relative` to the directory
+/// being extracted to, pointing to `target`
+#[cfg(target_family = "unix")]
+fn create_symlink(
+    relative: &Path,
+    target: &[u8],
+    path: &Path,
+    name: &str,
+) -> Result<(), ZipParseError> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    if !is_enclosed_symlink(relative, target) {
+        return Err(ZipParseError::UnsafePath(name.to_owned()));
+    }
+
+    std::os::unix::fs::symlink(OsStr::from_bytes(target), path)?;
+
+    Ok(())
+}
+
+#[cfg(not(target_family = "unix"))]
+fn create_symlink(
+    _relative: &Path,
+    _target: &[u8],
+    _path: &Path,
+    _name: &str,
+) -> Result<(), ZipParseError> {
+    unreachable!("symbolic links are only restored on unix")
+}
+
+/// Whether a link at `relative` pointing to `target` resolves inside the
+/// directory being extracted to.
+///
+/// Parent directory components are only accepted at the start of `target`, so
+/// that the link climbs only through the real directories containing it. Any
+/// other link it passes through afterwards has been held to the same rule.
+fn is_enclosed_symlink(relative: &Path, target: &[u8]) -> bool {
+    if target.is_empty() || target.starts_with(b"/") || target.contains(&0) {
+        return false;
+    }
+
+    let mut depth = relative.components().count() - 1;
+    let mut descended = false;
+
+    for component in target.split(|&b| b == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." if descended => return false,
+            b".." => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            _ => descended = true,
+        }
+    }
+
+    true
+}
+
+/// Create `relative` and each of its ancestors beneath `dir`, refusing to
+/// follow any symbolic link along the way
+fn create_dir_all(dir: &Path, relative: &Path) -> Result<(), ZipParseError> {
+    let mut path = dir.to_path_buf();
+
+    for component in relative.components() {
+        path.push(component);
+
+        match fs::symlink_metadata(&path) {
+            Ok(existing) if existing.is_dir() => {}
+            Ok(existing) if existing.file_type().is_symlink() => {
+                return Err(ZipParseError::UnsafePath(path.display().to_string()));
+            }
+            Ok(_) => return Err(io::Error::from(io::ErrorKind::AlreadyExists).into()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => fs::create_dir(&path)?,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert an entry name to a relative path that cannot escape the directory
+/// it is joined onto, or `None` if no such path exists. The path is empty for
+/// names such as `/` or `./`.
+fn sanitize_path(name: &str) -> Option<PathBuf> {
+    if name.contains('\0') {
+        return None;
+    }
+
+    let mut components = name.split(['/', '\\']).peekable();
+
+    // drive prefixes such as `C:`
+    if let Some(first) = components.peek() {
+        let bytes = first.as_bytes();
+
+        if bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+            components.next();
+        }
+    }
+
+    let mut path = PathBuf::new();
+
+    for component in components {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            // anything else containing `:` could be a drive or an NTFS
+            // alternate data stream on Windows
+            component if cfg!(windows) && component.contains(':') => return None,
+            component => path.push(component),
+        }
+    }
+
+    Some(path)
+}
+
+/// The number of bytes written so far across every extracted file
+#[derive(Debug)]
+struct Budget {
+    written: u64,
+    limit: Option<u64>,
+    /// See [`ExtractionLimits::max_ratio`]
+    max_ratio: Option<u32>,
+}
+
+impl Budget {
+    fn new(limits: &ExtractionLimits) -> Self {
+        Self {
+            written: 0,
+            limit: limits.total_size,
+            max_ratio: limits.max_ratio,
+        }
+    }
+
+    /// Set aside `size` bytes for a file written later, returning the limit on
+    /// what it may write
+    #[cfg(feature = "rayon")]
+    fn reserve(&mut self, size: u64) -> Result<Option<u64>, ZipParseError> {
+        let Some(limit) = self.limit else {
+            return Ok(None);
+        };
+
+        let total = self.written.saturating_add(size);
+
+        if total > limit {
+            return Err(ZipParseError::FileTooLarge(total));
+        }
+
+        self.written = total;
+
+        Ok(Some(size))
+    }
+}
+
+struct BudgetWriter<'b, W: Write + ?Sized> {
+    inner: &'b mut W,
+    budget: &'b mut Budget,
+    /// The number of bytes written of this file
+    written: u64,
+    /// The most bytes this file may produce given its compressed size
+    max_size: Option<u64>,
+    /// The error for the write which exceeded the budget
+    exceeded: Option<ZipParseError>,
+}
+
+impl<W: Write + ?Sized> Write for BudgetWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let total = self.budget.written + buf.len() as u64;
+
+        if self.budget.limit.is_some_and(|limit| total > limit) {
+            self.exceeded = Some(ZipParseError::FileTooLarge(total));
+        } else if let (Some(max_size), Some(ratio)) = (self.max_size, self.budget.max_ratio) {
+            if self.written + buf.len() as u64 > max_size {
+                self.exceeded = Some(ZipParseError::RatioLimitExceeded(ratio));
+            }
+        }
+
+        if self.exceeded.is_some() {
+            return Err(io::Error::other("extraction limit exceeded"));
+        }
+
+        let n = self.inner.write(buf)?;
+        self.budget.written += n as u64;
+        self.written += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        io::{Cursor, Write},
+        path::Path,
+    };
+
+    use crate::{
+        ExtractOptions, ExtractionLimits, FileOptions, Overwrite, ZipArchive, ZipParseError,
+        ZipWriter,
+    };
+
+    use super::{is_enclosed_symlink, sanitize_path};
+
+    fn build(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        for (name, contents) in files {
+            if name.ends_with('/') {
+                writer.add_directory(*name, FileOptions::default()).unwrap();
+            } else {
+                writer.start_file(*name, FileOptions::default()).unwrap();
+                writer.write_all(contents).unwrap();
+            }
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(sanitize_path("a/b.txt").unwrap(), Path::new("a/b.txt"));
+        assert_eq!(
+            sanitize_path("/etc/passwd").unwrap(),
+            Path::new("etc/passwd")
+        );
+        assert_eq!(sanitize_path("C:\\a\\.\\b").unwrap(), Path::new("a/b"));
+        assert_eq!(sanitize_path("dir/").unwrap(), Path::new("dir"));
+
+        assert!(sanitize_path("../a").is_none());
+        assert!(sanitize_path("a\\..\\..\\b").is_none());
+        assert!(sanitize_path("a/../b").is_none());
+        assert_eq!(sanitize_path("./").unwrap(), Path::new(""));
+        assert!(sanitize_path("a\0b").is_none());
+    }
+
+    #[test]
+    fn extract() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = build(&[
+            ("empty/", b""),
+            ("a/b/c.txt", b"hello"),
+            ("/absolute.txt", b"world"),
+        ]);
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        archive
+            .extract_to(dir.path(), ExtractOptions::default())
+            .unwrap();
+
+        assert!(dir.path().join("empty").is_dir());
+        assert_eq!(fs::read(dir.path().join("a/b/c.txt")).unwrap(), b"hello");
+        assert_eq!(fs::read(dir.path().join("absolute.txt")).unwrap(), b"world");
+
+        // existing files are not replaced by default
+        assert!(archive
+            .extract_to(dir.path(), ExtractOptions::default())
+            .is_err());
+
+        archive
+            .extract_to(
+                dir.path(),
+                ExtractOptions::default().overwrite(Overwrite::Skip),
+            )
+            .unwrap();
+
+        fs::write(dir.path().join("a/b/c.txt"), b"changed").unwrap();
+        archive
+            .extract_to(
+                dir.path(),
+                ExtractOptions::default().overwrite(Overwrite::Replace),
+            )
+            .unwrap();
+        assert_eq!(fs::read(dir.path().join("a/b/c.txt")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+
+        let archive = ZipArchive::from_buffer(build(&[("../evil", b"evil")])).unwrap();
+        assert!(matches!(
+            archive.extract_to(&target, ExtractOptions::default()),
+            Err(ZipParseError::UnsafePath(_))
+        ));
+        assert!(!dir.path().join("evil").exists());
+
+        #[cfg(target_family = "unix")]
+        {
+            std::os::unix::fs::symlink(dir.path(), target.join("link")).unwrap();
+
+            let archive = ZipArchive::from_buffer(build(&[("link/evil", b"evil")])).unwrap();
+            assert!(matches!(
+                archive.extract_to(&target, ExtractOptions::default()),
+                Err(ZipParseError::UnsafePath(_))
+            ));
+
+            let archive = ZipArchive::from_buffer(build(&[("link", b"evil")])).unwrap();
+            assert!(matches!(
+                archive.extract_to(
+                    &target,
+                    ExtractOptions::default().overwrite(Overwrite::Replace)
+                ),
+                Err(ZipParseError::UnsafePath(_))
+            ));
+
+            assert!(!dir.path().join("evil").exists());
+        }
+    }
+
+    #[test]
+    fn limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = build(&[("a", &[0; 100]), ("b", &[0; 100])]);
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let result = archive.extract_to(dir.path(), ExtractOptions::default().limit(Some(150)));
+
+        assert!(matches!(result, Err(ZipParseError::FileTooLarge(_))));
+        assert_eq!(fs::read(dir.path().join("a")).unwrap(), [0; 100]);
+        assert!(!dir.path().join("b").exists());
+    }
+
+    #[test]
+    fn extraction_limits() {
+        let buffer = build(&[("a", &[0; 10_000]), ("long name", b"b")]);
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        let extract = |limits: ExtractionLimits| {
+            let dir = tempfile::tempdir().unwrap();
+            let result = archive.extract_to(dir.path(), ExtractOptions::default().limits(limits));
+
+            (result, dir)
+        };
+
+        assert!(matches!(
+            extract(ExtractionLimits::default().max_files(Some(1))).0,
+            Err(ZipParseError::TooManyFiles { found: 2, limit: 1 })
+        ));
+        assert!(matches!(
+            extract(ExtractionLimits::default().max_name_len(Some(4))).0,
+            Err(ZipParseError::NameTooLong { found: 9, limit: 4 })
+        ));
+
+        // ten thousand zeros deflate to a few dozen bytes
+        let (result, dir) = extract(ExtractionLimits::default().max_ratio(Some(10)));
+        assert!(matches!(result, Err(ZipParseError::RatioLimitExceeded(10))));
+        assert!(!dir.path().join("a").exists());
+
+        let (result, dir) = extract(
+            ExtractionLimits::default()
+                .max_ratio(Some(1000))
+                .max_files(Some(2))
+                .max_name_len(Some(9))
+                .total_size(None),
+        );
+        result.unwrap();
+        assert_eq!(fs::read(dir.path().join("a")).unwrap(), [0; 10_000]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_extract() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = (0..64)
+            .map(|i| (format!("dir{}/{i}", i % 4), i.to_string().repeat(1000)))
+            .collect::<Vec<_>>();
+        let entries = files
+            .iter()
+            .map(|(name, contents)| (name.as_str(), contents.as_bytes()))
+            .collect::<Vec<_>>();
+
+        let archive = ZipArchive::from_buffer(build(&entries)).unwrap();
+        archive
+            .par_extract_to(dir.path(), ExtractOptions::default())
+            .unwrap();
+
+        for (name, contents) in &files {
+            assert_eq!(
+                fs::read(dir.path().join(name)).unwrap(),
+                contents.as_bytes()
+            );
+        }
+
+        assert!(matches!(
+            archive.par_extract_to(dir.path(), ExtractOptions::default()),
+            Err(ZipParseError::IoError(err)) if err.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_extract_duplicates() {
+        let buffer = build(&[("a", b"first"), ("b", b"b"), ("a", b"second")]);
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let options = ExtractOptions::default().overwrite(Overwrite::Replace);
+
+        let serial = tempfile::tempdir().unwrap();
+        archive.extract_to(serial.path(), options.clone()).unwrap();
+
+        for _ in 0..8 {
+            let parallel = tempfile::tempdir().unwrap();
+            archive
+                .par_extract_to(parallel.path(), options.clone())
+                .unwrap();
+
+            for name in ["a", "b"] {
+                assert_eq!(
+                    fs::read(parallel.path().join(name)).unwrap(),
+                    fs::read(serial.path().join(name)).unwrap()
+                );
+            }
+        }
+
+        assert_eq!(fs::read(serial.path().join("a")).unwrap(), b"second");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_extract_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = build(&[("a", &[0; 100]), ("b", &[0; 100])]);
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let result = archive.par_extract_to(dir.path(), ExtractOptions::default().limit(Some(150)));
+
+        assert!(matches!(result, Err(ZipParseError::FileTooLarge(200))));
+        assert_eq!(fs::read(dir.path().join("a")).unwrap(), [0; 100]);
+        assert!(!dir.path().join("b").exists());
+
+        // with several corrupt files, the first is always the one reported
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        for i in 0..16 {
+            writer
+                .start_file(
+                    i.to_string(),
+                    FileOptions::default().compression_method(crate::CompressionMethod::STORED),
+                )
+                .unwrap();
+            writer
+                .write_all(format!("contents {i:02}").as_bytes())
+                .unwrap();
+        }
+
+        let mut buffer = writer.finish().unwrap().into_inner();
+        for i in [3, 9, 12] {
+            let offset = memchr::memmem::find(&buffer, format!("contents {i:02}").as_bytes());
+            buffer[offset.unwrap()] = b'C';
+        }
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let expected = archive.header(3).unwrap().metadata.crc;
+
+        for _ in 0..8 {
+            let dir = tempfile::tempdir().unwrap();
+
+            assert!(matches!(
+                archive.par_extract_to(dir.path(), ExtractOptions::default()),
+                Err(ZipParseError::CrcMismatch { expected: crc, .. }) if crc == expected
+            ));
+            assert!(dir.path().join("2").exists());
+            assert!(!dir.path().join("3").exists());
+        }
+    }
+
+    #[test]
+    fn enclosed_symlinks() {
+        assert!(is_enclosed_symlink(Path::new("link"), b"target"));
+        assert!(is_enclosed_symlink(Path::new("a/b/link"), b"../../c/./d"));
+
+        assert!(!is_enclosed_symlink(Path::new("link"), b""));
+        assert!(!is_enclosed_symlink(Path::new("link"), b"/etc/passwd"));
+        assert!(!is_enclosed_symlink(Path::new("link"), b"../target"));
+        assert!(!is_enclosed_symlink(Path::new("a/link"), b"b/../.."));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn unix_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::{DateTimeModified, ExternalAttributes};
+
+        let dir = tempfile::tempdir().unwrap();
+        let modified = DateTimeModified::from_date_time(2020, 6, 15, 12, 30, 0).unwrap();
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        let options = |mode| {
+            FileOptions::default()
+                .external_attributes(ExternalAttributes::from_unix_mode(mode))
+                .date_time_modified(modified)
+        };
+
+        writer.add_directory("bin/", options(0o040700)).unwrap();
+        writer.start_file("bin/run", options(0o104755)).unwrap();
+        writer.write_all(b"#!/bin/sh").unwrap();
+        writer.start_file("run", options(0o120777)).unwrap();
+        writer.write_all(b"bin/run").unwrap();
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        assert_eq!(archive.header(1).unwrap().unix_mode(), Some(0o104755));
+        assert!(archive.header(2).unwrap().is_symlink());
+
+        // by default, nothing is restored
+        archive
+            .extract_to(dir.path().join("plain"), ExtractOptions::default())
+            .unwrap();
+
+        let link = fs::symlink_metadata(dir.path().join("plain/run")).unwrap();
+        assert!(link.is_file());
+        assert_eq!(fs::read(dir.path().join("plain/run")).unwrap(), b"bin/run");
+
+        let options = ExtractOptions::default()
+            .permissions(true)
+            .symlinks(true)
+            .modified_times(true);
+        archive
+            .extract_to(dir.path().join("restored"), options.clone())
+            .unwrap();
+
+        let run = fs::metadata(dir.path().join("restored/bin/run")).unwrap();
+        assert_eq!(run.permissions().mode() & 0o7777, 0o755);
+        assert_eq!(run.modified().unwrap(), modified.to_system_time().unwrap());
+
+        let bin = fs::metadata(dir.path().join("restored/bin")).unwrap();
+        assert_eq!(bin.permissions().mode() & 0o7777, 0o700);
+        assert_eq!(bin.modified().unwrap(), modified.to_system_time().unwrap());
+
+        let link = dir.path().join("restored/run");
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("bin/run"));
+        assert_eq!(fs::read(&link).unwrap(), b"#!/bin/sh");
+
+        // links pointing outside of the directory are refused
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer
+            .start_file(
+                "escape",
+                FileOptions::default()
+                    .external_attributes(ExternalAttributes::from_unix_mode(0o120777)),
+            )
+            .unwrap();
+        writer.write_all(b"../../etc/passwd").unwrap();
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        assert!(matches!(
+            archive.extract_to(dir.path().join("escape"), options),
+            Err(ZipParseError::UnsafePath(_))
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{self, BufRead};
+
+use crate::bits::{invalid_data, BitReader};
+
+/// Longest code used by any supported method. Deflate codes are at most 15
+/// bits, while the Shannon-Fano codes of Implode may be 16.
+const MAX_CODE_LEN: usize = 16;
+
+/// A canonical Huffman code, decoded one bit at a time so that no input past
+/// the end of the stream is consumed
+#[derive(Debug)]
+pub(crate) struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; MAX_CODE_LEN + 1],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build a code from the length of each symbol's code, where a length of
+    /// zero means the symbol is unused
+    pub(crate) fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0; MAX_CODE_LEN + 1];
+
+        for &len in lengths {
+            if usize::from(len) > MAX_CODE_LEN {
+                return Err(invalid_data("huffman code too long"));
+            }
+
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        // reject codes with more symbols than fit in their lengths
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+
+            if left < 0 {
+                return Err(invalid_data("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0; MAX_CODE_LEN + 2];
+        for len in 1..=MAX_CODE_LEN {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; usize::from(offsets[MAX_CODE_LEN + 1])];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                let offset = &mut offsets[usize::from(len)];
+                symbols[usize::from(*offset)] = symbol as u16;
+                *offset += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    /// Decode a single symbol, whose code is stored most significant bit first
+    pub(crate) fn decode<R: BufRead>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        self.decode_bits(bits, false)
+    }
+
+    /// Decode a single symbol whose code is stored with every bit inverted.
+    ///
+    /// The Shannon-Fano codes used by Implode assign the longest codes the
+    /// smallest values, which makes them canonical Huffman codes once inverted.
+    pub(crate) fn decode_inverted<R: BufRead>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        self.decode_bits(bits, true)
+    }
+
+    fn decode_bits<R: BufRead>(&self, bits: &mut BitReader<R>, invert: bool) -> io::Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for &count in &self.counts[1..] {
+            code |= i32::from(bits.bit()? != invert);
+            let count = i32::from(count);
+
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid_data("invalid huffman code"))
+    }
+}
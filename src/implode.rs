@@ -0,0 +1,272 @@
+//! A decoder for Implode, the LZ77 method of PKZIP 1.
+//!
+//! Back references are made of a distance, whose high bits are Shannon-Fano
+//! coded, and a Shannon-Fano coded length. Literals are either stored as is, or
+//! coded with a third tree. Two of the general purpose flags select the
+//! variant: [`COMPRESSION_OPTION1`] for an 8 KiB rather than 4 KiB window, and
+//! [`COMPRESSION_OPTION2`] for the literal tree.
+//!
+//! [`COMPRESSION_OPTION1`]: crate::ZipFlags::COMPRESSION_OPTION1
+//! [`COMPRESSION_OPTION2`]: crate::ZipFlags::COMPRESSION_OPTION2
+
+use std::io::{self, BufRead, Read};
+
+use crate::{
+    bits::{invalid_data, BitReader},
+    huffman::Huffman,
+};
+
+const WINDOW_SIZE: usize = 1 << 13;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+
+/// The length symbol which is followed by an extra byte of length
+const MAX_LENGTH_SYMBOL: u16 = 63;
+
+#[derive(Debug)]
+struct Trees {
+    literals: Option<Huffman>,
+    lengths: Huffman,
+    distances: Huffman,
+}
+
+/// Read a tree of `symbol_count` symbols. The code lengths are run length
+/// encoded: after a byte holding the number of runs less one, each byte
+/// holds the length less one in its low nibble and the number of symbols
+/// of that length less one in its high nibble.
+fn read_tree<R: BufRead>(bits: &mut BitReader<R>, symbol_count: usize) -> io::Result<Huffman> {
+    let runs = bits.bits(8)? + 1;
+    let mut lengths = Vec::with_capacity(symbol_count);
+
+    for _ in 0..runs {
+        let run = bits.bits(8)? as u8;
+        let len = (run & 0xf) + 1;
+        let count = usize::from(run >> 4) + 1;
+
+        if lengths.len() + count > symbol_count {
+            return Err(invalid_data("too many shannon-fano code lengths"));
+        }
+
+        lengths.resize(lengths.len() + count, len);
+    }
+
+    if lengths.len() != symbol_count {
+        return Err(invalid_data("too few shannon-fano code lengths"));
+    }
+
+    Huffman::new(&lengths)
+}
+
+fn read_trees<R: BufRead>(bits: &mut BitReader<R>, literal_tree: bool) -> io::Result<Trees> {
+    let literals = if literal_tree {
+        Some(read_tree(bits, 256)?)
+    } else {
+        None
+    };
+
+    Ok(Trees {
+        literals,
+        lengths: read_tree(bits, 64)?,
+        distances: read_tree(bits, 64)?,
+    })
+}
+
+/// Streaming Implode decoder, which stops once `uncompressed_size` bytes have
+/// been produced
+#[derive(Debug)]
+pub(crate) struct ImplodeDecoder<R> {
+    bits: BitReader<R>,
+    large_window: bool,
+    literal_tree: bool,
+    /// The Shannon-Fano trees, read before the first output
+    trees: Option<Trees>,
+    window: Vec<u8>,
+    written: u64,
+    /// A back reference which has not been fully copied out yet
+    copy_len: usize,
+    copy_distance: usize,
+    remaining: u64,
+}
+
+impl<R: BufRead> ImplodeDecoder<R> {
+    pub(crate) fn new(
+        reader: R,
+        large_window: bool,
+        literal_tree: bool,
+        uncompressed_size: u64,
+    ) -> Self {
+        Self {
+            bits: BitReader::new(reader),
+            large_window,
+            literal_tree,
+            trees: None,
+            window: vec![0; WINDOW_SIZE],
+            written: 0,
+            copy_len: 0,
+            copy_distance: 0,
+            remaining: uncompressed_size,
+        }
+    }
+
+    /// Decode the next literal, or start copying the next back reference
+    fn decode_next(&mut self) -> io::Result<Option<u8>> {
+        let trees = match &mut self.trees {
+            Some(trees) => trees,
+            trees @ None => trees.insert(read_trees(&mut self.bits, self.literal_tree)?),
+        };
+
+        if self.bits.bit()? {
+            let byte = match &trees.literals {
+                Some(literals) => literals.decode_inverted(&mut self.bits)? as u8,
+                None => self.bits.bits(8)? as u8,
+            };
+
+            return Ok(Some(byte));
+        }
+
+        let distance_low_bits = if self.large_window { 7 } else { 6 };
+        let distance_low = self.bits.bits(distance_low_bits)? as usize;
+        let distance_high = usize::from(trees.distances.decode_inverted(&mut self.bits)?);
+
+        // with a literal tree, literals are cheap enough that two byte matches
+        // are never worth it
+        let min_len = if self.literal_tree { 3 } else { 2 };
+
+        let symbol = trees.lengths.decode_inverted(&mut self.bits)?;
+        let mut len = usize::from(symbol) + min_len;
+        if symbol == MAX_LENGTH_SYMBOL {
+            len += self.bits.bits(8)? as usize;
+        }
+
+        self.copy_len = len;
+        self.copy_distance = (distance_high << distance_low_bits | distance_low) + 1;
+
+        Ok(None)
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.window[self.written as usize & WINDOW_MASK] = byte;
+        self.written += 1;
+    }
+}
+
+impl<R: BufRead> Read for ImplodeDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        while n < buf.len() && self.remaining > 0 {
+            let byte = if self.copy_len > 0 {
+                self.copy_len -= 1;
+
+                // references before the start of the file read as zeros,
+                // which the window is initialized to
+                self.window[(self.written as usize).wrapping_sub(self.copy_distance) & WINDOW_MASK]
+            } else {
+                match self.decode_next()? {
+                    Some(byte) => byte,
+                    None => continue,
+                }
+            };
+
+            self.push(byte);
+            buf[n] = byte;
+            n += 1;
+            self.remaining -= 1;
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::bits::BitWriter;
+
+    use super::ImplodeDecoder;
+
+    /// Implode `"abc"`, a back reference to it, a reference to before the start
+    /// of the file and then `"z"`, using trees in which every code has the same
+    /// length
+    fn implode(large_window: bool, literal_tree: bool) -> Vec<u8> {
+        let mut w = BitWriter::default();
+
+        if literal_tree {
+            // 16 runs of 16 codes of 8 bits
+            w.bits(15, 8);
+            for _ in 0..16 {
+                w.bits(0xf7, 8);
+            }
+        }
+
+        // the length and distance trees, each 4 runs of 16 codes of 6 bits
+        for _ in 0..2 {
+            w.bits(3, 8);
+            for _ in 0..4 {
+                w.bits(0xf5, 8);
+            }
+        }
+
+        let literal = |w: &mut BitWriter, byte: u8| {
+            w.bits(1, 1);
+
+            // Shannon-Fano codes are stored inverted
+            if literal_tree {
+                w.code(u32::from(!byte), 8);
+            } else {
+                w.bits(u32::from(byte), 8);
+            }
+        };
+
+        let reference = |w: &mut BitWriter, distance: u32, len: u32| {
+            let distance_low_bits = if large_window { 7 } else { 6 };
+            let min_len = if literal_tree { 3 } else { 2 };
+
+            w.bits(0, 1);
+            w.bits(
+                (distance - 1) & ((1 << distance_low_bits) - 1),
+                distance_low_bits,
+            );
+            w.code(!((distance - 1) >> distance_low_bits) & 63, 6);
+
+            let symbol = (len - min_len).min(63);
+            w.code(!symbol & 63, 6);
+            if symbol == 63 {
+                w.bits(len - min_len - 63, 8);
+            }
+        };
+
+        for &byte in b"abc" {
+            literal(&mut w, byte);
+        }
+        reference(&mut w, 3, 7);
+        reference(&mut w, 4000, 100);
+        literal(&mut w, b'z');
+
+        w.bytes
+    }
+
+    #[test]
+    fn explode() {
+        let mut expected = b"abcabcabca".to_vec();
+        expected.extend([0; 100]);
+        expected.push(b'z');
+
+        for large_window in [false, true] {
+            for literal_tree in [false, true] {
+                let compressed = implode(large_window, literal_tree);
+
+                let mut decoder = ImplodeDecoder::new(
+                    compressed.as_slice(),
+                    large_window,
+                    literal_tree,
+                    expected.len() as u64,
+                );
+                let mut out = Vec::new();
+                decoder.read_to_end(&mut out).unwrap();
+
+                assert_eq!(out, expected, "{large_window} {literal_tree}");
+            }
+        }
+    }
+}
@@ -3,7 +3,7 @@
  *
  * ```no_run
  * # use zip_rs::ZipArchive;
- * let mut archive = ZipArchive::from_path("./foo.zip").unwrap();
+ * let archive = ZipArchive::from_path("./foo.zip").unwrap();
  *
  * for file in archive.files() {
  *   // ...
@@ -15,11 +15,15 @@
 
 use std::{
     borrow::Cow,
+    collections::HashMap,
     ffi::OsStr,
     fs::File,
     io::{Read, Write},
     ops::Deref,
+    ops::Range,
     path::Path,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
 };
 
 #[cfg(target_family = "unix")]
@@ -29,26 +33,75 @@ use std::os::unix::ffi::OsStrExt;
 use std::os::windows::ffi::OsStrExt;
 
 pub use common::*;
+use decompress::{Crc32Reader, DecoderOptions, SizeLimitReader};
+pub use decompress::{DecoderRegistry, DEFAULT_PPMD_MEMORY_LIMIT};
 pub use error::ZipParseError;
-use flate2::read::DeflateDecoder;
-use parse::Parser;
+pub use extra::{
+    ExtraField, ExtraFields, Timestamps, UnicodeExtraField, WinZipAesExtraField, Zip64ExtraField,
+};
+pub use extract::{ExtractOptions, ExtractionLimits, Overwrite};
+use parse::{range_within, Parser, StoredCentralDirectory};
+pub use seek::{ZipFileReader, ZipReader};
+pub use stream::{ZipStreamEntry, ZipStreamReader};
+#[cfg(feature = "aes")]
+pub use winzip_aes::AesStrength;
+pub use write::{FileOptions, ZipStreamWriter, ZipWriter};
 
+mod bits;
 mod common;
+mod cp437;
+mod decompress;
+mod deflate64;
 mod error;
+mod extra;
+mod extract;
+mod huffman;
+mod implode;
 mod parse;
+mod reduce;
+mod seek;
+mod shrink;
+mod stream;
+#[cfg(feature = "aes")]
+mod winzip_aes;
+mod write;
+mod zipcrypto;
 
 const KB: usize = 1024;
 const MB: usize = 1024 * KB;
 const GB: usize = 1024 * MB;
 
-/// An entire ZIP archive file
+/// An entire ZIP archive file.
+///
+/// Files are read from the buffer without any shared state, so an archive may
+/// be shared between threads, for example through an [`Arc`], and read from
+/// all of them at once.
+///
+/// The files borrow from the archive. Where that isn't possible, an archive in
+/// an [`Arc`] can hand out [`OwnedZipFile`]s, which keep it alive instead. The
+/// buffer may be anything which dereferences to bytes, such as a [`Vec<u8>`],
+/// an `Arc<[u8]>` or a `bytes::Bytes`.
+///
+/// Files can't outlive the archive they borrow from:
+///
+/// ```compile_fail
+/// # use zip_rs::ZipArchive;
+/// let file = {
+///     let archive = ZipArchive::from_buffer(Vec::new()).unwrap();
+///     archive.by_index(0).unwrap()
+/// };
+/// ```
 #[derive(Debug)]
-pub struct ZipArchive<'a, B: Deref<Target = [u8]>> {
-    pub central_directory: CentralDirectory<'a>,
-    parser: Parser<B>,
+pub struct ZipArchive<B: Deref<Target = [u8]>> {
+    buffer: B,
+    central_directory: StoredCentralDirectory,
+    decoder_options: DecoderOptions,
+    /// Maps file names to their index in the central directory, built the
+    /// first time a file is looked up by name
+    name_index: OnceLock<HashMap<Box<[u8]>, usize>>,
 }
 
-impl<'a> ZipArchive<'a, memmap::Mmap> {
+impl ZipArchive<memmap::Mmap> {
     /// Parse a [`ZipArchive`] from a file path. The contents are memory mapped.
     pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ZipParseError> {
         let file = File::open(path)?;
@@ -58,29 +111,161 @@ impl<'a> ZipArchive<'a, memmap::Mmap> {
     }
 }
 
-impl<'a, B: Deref<Target = [u8]>> ZipArchive<'a, B> {
+impl<B: Deref<Target = [u8]>> ZipArchive<B> {
     /// Parse a [`ZipArchive`] from an existing buffer in memory
     pub fn from_buffer(buffer: B) -> Result<Self, ZipParseError> {
-        let mut parser = Parser::new(buffer);
-
-        let central_directory = parser.parse_central_directory()?;
+        let central_directory = Parser::new(&buffer).parse_central_directory()?;
+        let central_directory = StoredCentralDirectory::new(&buffer, central_directory);
 
         Ok(ZipArchive {
+            buffer,
             central_directory,
-            parser,
+            decoder_options: DecoderOptions::default(),
+            name_index: OnceLock::new(),
         })
     }
 
+    /// Decoders for compression methods this crate doesn't support itself,
+    /// used by every file read from this archive
+    pub fn set_decoders(&mut self, decoders: DecoderRegistry) {
+        self.decoder_options.registry = decoders;
+    }
+
+    /// The password used to decrypt encrypted files read from this archive.
+    /// See [`CompressedZipFile::password`].
+    pub fn set_password(&mut self, password: &[u8]) {
+        self.decoder_options.password = Some(password.to_vec());
+    }
+
+    /// The central directory, which lists every file in this archive.
+    ///
+    /// This collects the headers of every file, so prefer
+    /// [`ZipArchive::header`] to look at only a few of them.
+    pub fn central_directory(&self) -> CentralDirectory<'_> {
+        self.central_directory.load(&self.buffer)
+    }
+
+    /// The central directory header of the file at `index`
+    pub fn header(&self, index: usize) -> Option<CentralDirectoryFileHeader<'_>> {
+        self.central_directory.header(&self.buffer, index)
+    }
+
+    /// The number of files in this archive
+    pub fn len(&self) -> usize {
+        self.central_directory.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Lazily iterate over the files in this archive
-    pub fn files<'b>(
-        &'b mut self,
-    ) -> impl Iterator<Item = Result<CompressedZipFile<'a>, ZipParseError>> + 'b {
-        // todo: can we avoid this clone by wrapping self.parser in RefCell?
-        let files = self.central_directory.files.clone();
+    pub fn files(&self) -> impl Iterator<Item = Result<CompressedZipFile<'_>, ZipParseError>> {
+        (0..self.len()).map(|index| self.by_index(index))
+    }
 
-        files
-            .into_iter()
-            .map(move |file_header| self.parser.read_file(&file_header))
+    /// The index of the file named `name` in the central directory.
+    ///
+    /// If several files share a name, the last one is used, as it is the one
+    /// most recently appended to the archive.
+    pub fn index_of(&self, name: &[u8]) -> Option<usize> {
+        let index = self.name_index.get_or_init(|| {
+            self.central_directory()
+                .files
+                .iter()
+                .enumerate()
+                .map(|(i, header)| (header.metadata.name.into(), i))
+                .collect()
+        });
+
+        index.get(name).copied()
+    }
+
+    /// Read the file at `index` in the central directory
+    pub fn by_index(&self, index: usize) -> Result<CompressedZipFile<'_>, ZipParseError> {
+        let header = self
+            .header(index)
+            .ok_or(ZipParseError::Generic("file index out of bounds"))?;
+
+        let file = Parser::new(&self.buffer).read_file(&header)?;
+
+        Ok(file.decoder_options(self.decoder_options.clone()))
+    }
+
+    /// Read the file named `name`. See [`ZipArchive::index_of`].
+    pub fn by_name(&self, name: &[u8]) -> Result<CompressedZipFile<'_>, ZipParseError> {
+        let index = self
+            .index_of(name)
+            .ok_or_else(|| ZipParseError::FileNotFound(String::from_utf8_lossy(name).into()))?;
+
+        self.by_index(index)
+    }
+
+    /// Read the file at `index`, as an [`OwnedZipFile`] which keeps this
+    /// archive alive
+    pub fn by_index_owned(
+        self: &Arc<Self>,
+        index: usize,
+    ) -> Result<OwnedZipFile<B>, ZipParseError> {
+        let file = self.by_index(index)?;
+
+        Ok(OwnedZipFile {
+            name: range_within(&self.buffer, file.metadata.name),
+            extra_field: range_within(&self.buffer, file.metadata.extra_field),
+            contents: range_within(&self.buffer, file.contents),
+            metadata: Metadata {
+                name: &[],
+                extra_field: &[],
+                ..file.metadata
+            },
+            archive: Arc::clone(self),
+        })
+    }
+
+    /// Read the file named `name`, as an [`OwnedZipFile`] which keeps this
+    /// archive alive. See [`ZipArchive::index_of`].
+    pub fn by_name_owned(self: &Arc<Self>, name: &[u8]) -> Result<OwnedZipFile<B>, ZipParseError> {
+        let index = self
+            .index_of(name)
+            .ok_or_else(|| ZipParseError::FileNotFound(String::from_utf8_lossy(name).into()))?;
+
+        self.by_index_owned(index)
+    }
+}
+
+/// A file which keeps its [`ZipArchive`] alive rather than borrowing from it,
+/// for when the file must be `'static`, such as when it is sent to another
+/// thread
+#[derive(Debug)]
+pub struct OwnedZipFile<B: Deref<Target = [u8]>> {
+    archive: Arc<ZipArchive<B>>,
+    /// The file's metadata, with its borrowed fields left empty
+    metadata: Metadata<'static>,
+    name: Range<usize>,
+    extra_field: Range<usize>,
+    contents: Range<usize>,
+}
+
+impl<B: Deref<Target = [u8]>> OwnedZipFile<B> {
+    /// Borrow the file, to read its metadata or decompress it
+    pub fn file(&self) -> CompressedZipFile<'_> {
+        let buffer = &*self.archive.buffer;
+
+        CompressedZipFile {
+            metadata: Metadata {
+                name: &buffer[self.name.clone()],
+                extra_field: &buffer[self.extra_field.clone()],
+                ..self.metadata.clone()
+            },
+            contents: &buffer[self.contents.clone()],
+            verify_crc: true,
+            decoder_options: self.archive.decoder_options.clone(),
+        }
+    }
+
+    /// The archive this file was read from
+    pub fn archive(&self) -> &Arc<ZipArchive<B>> {
+        &self.archive
     }
 }
 
@@ -88,22 +273,91 @@ impl<'a, B: Deref<Target = [u8]>> ZipArchive<'a, B> {
 pub struct CentralDirectoryFileHeader<'a> {
     pub os: Os,
     pub metadata: Metadata<'a>,
-    pub disk_num_start: u16,
+    pub disk_num_start: u32,
     pub internal_attributes: InternalAttributes,
     pub external_attributes: ExternalAttributes,
     pub zip_specification_version: u8,
-    pub local_header_offset: u32,
+    pub local_header_offset: u64,
     pub comment: &'a [u8],
 }
 
-#[derive(Debug)]
+impl<'a> CentralDirectoryFileHeader<'a> {
+    /// The Unix mode of this file, including both the file type and permission
+    /// bits, if it was created on Unix or macOS
+    pub fn unix_mode(&self) -> Option<u32> {
+        match self.os.name() {
+            OsName::Unix | OsName::Darwin => {
+                Some(self.external_attributes.unix_mode()).filter(|&mode| mode != 0)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this file is a symbolic link, whose contents are the path it
+    /// links to
+    pub fn is_symlink(&self) -> bool {
+        self.unix_mode().is_some_and(|mode| {
+            mode & ExternalAttributes::UNIX_FILE_TYPE == ExternalAttributes::UNIX_SYMLINK
+        })
+    }
+
+    /// This file's comment, decoded as text in the same way as
+    /// [`Metadata::file_name`]
+    pub fn comment_text(&self) -> Cow<'a, str> {
+        decode_text(
+            self.comment,
+            self.metadata.flags,
+            self.metadata.extra_field,
+            UNICODE_COMMENT_EXTRA_FIELD_ID,
+        )
+    }
+}
+
+/// Decode a name or comment, preferring the value of the Info-ZIP Unicode
+/// extra field with header ID `unicode_extra_field_id` if it matches `raw`
+fn decode_text<'a>(
+    raw: &'a [u8],
+    flags: ZipFlags,
+    extra_field: &'a [u8],
+    unicode_extra_field_id: u16,
+) -> Cow<'a, str> {
+    if flags.has_language_encoding() {
+        return String::from_utf8_lossy(raw);
+    }
+
+    let unicode = extra::find_extra_field(extra_field, unicode_extra_field_id)
+        .and_then(
+            |data| match ExtraField::parse(unicode_extra_field_id, data) {
+                Ok(ExtraField::UnicodePath(field) | ExtraField::UnicodeComment(field)) => {
+                    Some(field)
+                }
+                _ => None,
+            },
+        )
+        .filter(|field| field.matches(raw))
+        .and_then(|field| std::str::from_utf8(field.value).ok());
+
+    match unicode {
+        Some(unicode) => Cow::Borrowed(unicode),
+        None => cp437::decode(raw),
+    }
+}
+
+/// The end of central directory record.
+///
+/// For zip64 archives, the values are taken from the zip64 end of central
+/// directory record rather than the saturated 16 and 32-bit fields of the
+/// classic record.
+#[derive(Debug, Clone)]
 pub struct EndCentralDirectory {
-    pub disk_num: u16,
-    pub disk_central_dir_num: u16,
-    pub disk_entries: u16,
-    pub total_entries: u16,
-    pub central_dir_size: u32,
-    pub central_dir_offset: u32,
+    pub disk_num: u32,
+    pub disk_central_dir_num: u32,
+    pub disk_entries: u64,
+    pub total_entries: u64,
+    pub central_dir_size: u64,
+    pub central_dir_offset: u64,
+    /// Whether a zip64 end of central directory record was present
+    pub is_zip64: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -119,11 +373,91 @@ pub struct Metadata<'a> {
     pub crc: u32,
 }
 
+impl<'a> Metadata<'a> {
+    /// This file's path inside the ZIP archive, decoded as text.
+    ///
+    /// Names are UTF-8 if [`ZipFlags::LANGUAGE_ENCODING`] is set, and
+    /// otherwise IBM code page 437, unless an up-to-date Info-ZIP Unicode path
+    /// extra field is present.
+    ///
+    /// Note that this path may reference file paths outside the archive through
+    /// the use of absolute paths or the parent directory (`..`). The full file path
+    /// should not be used when interacting with the host file system if the ZIP
+    /// file is untrusted.
+    pub fn file_name(&self) -> Cow<'a, str> {
+        decode_text(
+            self.name,
+            self.flags,
+            self.extra_field,
+            UNICODE_PATH_EXTRA_FIELD_ID,
+        )
+    }
+
+    /// Iterate over the records of this file's extra field
+    pub fn extra_fields(&self) -> ExtraFields<'a> {
+        ExtraFields::new(self.extra_field)
+    }
+
+    /// The WinZip AES extra field, present on files encrypted with AES
+    pub fn winzip_aes(&self) -> Option<WinZipAesExtraField> {
+        extra::find_extra_field(self.extra_field, WINZIP_AES_EXTRA_FIELD_ID)
+            .and_then(|data| WinZipAesExtraField::parse(data).ok())
+    }
+
+    /// The method the contents were compressed with. This is
+    /// [`Metadata::compression_method`], except for files encrypted with
+    /// WinZip AES, whose real method is stored in their extra field.
+    pub fn real_compression_method(&self) -> CompressionMethod {
+        match self.winzip_aes() {
+            Some(aes) if self.compression_method == CompressionMethod::AES => {
+                aes.compression_method
+            }
+            _ => self.compression_method,
+        }
+    }
+
+    /// Whether [`Metadata::crc`] holds the CRC-32 of the contents. AE-2
+    /// encrypted files leave it zeroed, relying on their authentication code
+    /// instead.
+    pub fn has_crc(&self) -> bool {
+        !matches!(
+            self.winzip_aes(),
+            Some(aes) if self.compression_method == CompressionMethod::AES
+                && aes.vendor_version == 2
+        )
+    }
+
+    /// The time this file was last modified.
+    ///
+    /// This is taken from the NTFS or extended timestamp extra fields when
+    /// present, and otherwise from the MS-DOS date and time, which has a 2
+    /// second resolution and no time zone.
+    pub fn modified(&self) -> Option<SystemTime> {
+        Timestamps::from_extra_field(self.extra_field)
+            .modified
+            .or_else(|| self.date_time_modified.to_system_time())
+    }
+
+    /// The time this file was last accessed, if stored in the NTFS or extended
+    /// timestamp extra fields
+    pub fn accessed(&self) -> Option<SystemTime> {
+        Timestamps::from_extra_field(self.extra_field).accessed
+    }
+
+    /// The time this file was created, if stored in the NTFS or extended
+    /// timestamp extra fields
+    pub fn created(&self) -> Option<SystemTime> {
+        Timestamps::from_extra_field(self.extra_field).created
+    }
+}
+
 /// A single compressed ZIP file
 #[derive(Debug)]
 pub struct CompressedZipFile<'a> {
     pub metadata: Metadata<'a>,
     contents: &'a [u8],
+    verify_crc: bool,
+    decoder_options: DecoderOptions,
 }
 
 impl<'a> CompressedZipFile<'a> {
@@ -131,38 +465,135 @@ impl<'a> CompressedZipFile<'a> {
         self.contents
     }
 
+    /// Whether to check the CRC-32 of the decompressed contents against
+    /// [`Metadata::crc`], returning [`ZipParseError::CrcMismatch`] if they
+    /// differ. Defaults to `true`.
+    pub fn verify_crc(mut self, verify_crc: bool) -> Self {
+        self.verify_crc = verify_crc;
+        self
+    }
+
+    /// The most memory, in bytes, a PPMd model may use. Files asking for more
+    /// fail with [`ZipParseError::MemoryLimitExceeded`] rather than being
+    /// decompressed. Defaults to [`DEFAULT_PPMD_MEMORY_LIMIT`].
+    pub fn ppmd_memory_limit(mut self, limit: usize) -> Self {
+        self.decoder_options.ppmd_memory_limit = limit;
+        self
+    }
+
+    /// Decoders for compression methods this crate doesn't support itself. See
+    /// [`DecoderRegistry`].
+    pub fn decoders(mut self, decoders: DecoderRegistry) -> Self {
+        self.decoder_options.registry = decoders;
+        self
+    }
+
+    /// The password used to decrypt this file, if it is encrypted. Without one,
+    /// decompressing an encrypted file fails with
+    /// [`ZipParseError::PasswordRequired`].
+    pub fn password(mut self, password: &[u8]) -> Self {
+        self.decoder_options.password = Some(password.to_vec());
+        self
+    }
+
+    /// Whether the contents are encrypted, and so need a password to be
+    /// decompressed
+    pub fn is_encrypted(&self) -> bool {
+        self.metadata.flags.is_encrypted()
+    }
+
+    pub(crate) fn decoder_options(mut self, options: DecoderOptions) -> Self {
+        self.decoder_options = options;
+        self
+    }
+
+    /// A reader over the decompressed contents, which fails if they are larger
+    /// than [`Metadata::uncompressed_size`]
+    pub(crate) fn decoder(&self) -> Result<SizeLimitReader<Box<dyn Read + 'a>>, ZipParseError> {
+        let decoder = decompress::decoder(&self.metadata, self.contents, &self.decoder_options)?;
+
+        Ok(SizeLimitReader::new(
+            decoder,
+            self.metadata.uncompressed_size,
+        ))
+    }
+
+    /// Whether the contents can be used as they are, without decompressing or
+    /// decrypting them
+    fn is_plain(&self) -> bool {
+        self.metadata.compression_method.name() == CompressionMethodName::None
+            && !self.is_encrypted()
+            && self.contents.len() as u64 == self.metadata.uncompressed_size
+    }
+
+    /// Decompress the contents into `w`, returning their CRC-32
+    fn decompress_to(&self, w: &mut dyn Write) -> Result<u32, ZipParseError> {
+        let mut decoder = Crc32Reader::new(self.decoder()?);
+
+        let amt_read = std::io::copy(&mut decoder, w)?;
+
+        if amt_read != self.metadata.uncompressed_size {
+            return Err(ZipParseError::Generic("failed to write full buffer"));
+        }
+
+        Ok(decoder.crc())
+    }
+
+    /// The capacity to start decompressing into. The declared size can't be
+    /// trusted, so beyond 1 MiB the buffer only grows as contents are produced.
+    fn initial_capacity(&self) -> usize {
+        self.metadata.uncompressed_size.min(MB as u64) as usize
+    }
+
+    fn check_limit(&self, limit: Option<usize>) -> Result<(), ZipParseError> {
+        if limit.is_some_and(|limit| self.metadata.uncompressed_size > limit as u64) {
+            return Err(ZipParseError::FileTooLarge(self.metadata.uncompressed_size));
+        }
+
+        Ok(())
+    }
+
+    fn check_crc(&self, found: u32) -> Result<(), ZipParseError> {
+        if self.verify_crc && self.metadata.has_crc() && found != self.metadata.crc {
+            return Err(ZipParseError::CrcMismatch {
+                expected: self.metadata.crc,
+                found,
+            });
+        }
+
+        Ok(())
+    }
+
     /// Efficiently writes decompressed contents to sink without loading full
     /// decompressed contents into memory
     ///
     /// `limit` controls the max uncompressed file size that will be accepted. A
     /// `limit` of `None` implies no limit. Note that setting too high of a limit
     /// can make decoders susceptible to DoS through ZIP bombs or other means.
+    ///
+    /// The decompressed contents are never larger than
+    /// [`Metadata::uncompressed_size`], as decompression fails as soon as the
+    /// decoder produces more.
+    ///
+    /// The CRC-32 of the contents can only be checked once they have been
+    /// fully written, so `w` may have received corrupt data when this returns
+    /// [`ZipParseError::CrcMismatch`].
     pub fn write_with_limit(
         &self,
         w: &mut dyn Write,
         limit: Option<usize>,
     ) -> Result<(), ZipParseError> {
-        if Some(self.metadata.uncompressed_size as usize) >= limit {
-            return Err(ZipParseError::FileTooLarge(self.metadata.uncompressed_size));
-        }
-
-        match self.metadata.compression_method.name() {
-            CompressionMethodName::None => {
-                w.write_all(self.contents)?;
-            }
-            CompressionMethodName::Deflate => {
-                let mut decoder = DeflateDecoder::new(self.contents);
+        self.check_limit(limit)?;
 
-                let amt_read = std::io::copy(&mut decoder, w)?;
+        let crc = if self.is_plain() {
+            w.write_all(self.contents)?;
 
-                if amt_read != self.metadata.uncompressed_size {
-                    return Err(ZipParseError::Generic("failed to write full buffer"));
-                }
-            }
-            method => todo!("unimplemented compression method {:?}", method),
-        }
+            crc32fast::hash(self.contents)
+        } else {
+            self.decompress_to(w)?
+        };
 
-        Ok(())
+        self.check_crc(crc)
     }
 
     /// Efficiently writes decompressed contents to sink without loading full
@@ -182,22 +613,19 @@ impl<'a> CompressedZipFile<'a> {
     pub fn decompressed_contents_with_limit(
         &self,
         limit: Option<usize>,
-    ) -> Result<Cow<[u8]>, ZipParseError> {
-        if Some(self.metadata.uncompressed_size as usize) >= limit {
-            return Err(ZipParseError::FileTooLarge(self.metadata.uncompressed_size));
-        }
-
-        match self.metadata.compression_method.name() {
-            CompressionMethodName::None => return Ok(Cow::Borrowed(self.contents)),
-            CompressionMethodName::Deflate => {
-                let mut out = vec![0; self.metadata.uncompressed_size as usize];
+    ) -> Result<Cow<'_, [u8]>, ZipParseError> {
+        self.check_limit(limit)?;
 
-                DeflateDecoder::new(self.contents).read_exact(&mut out)?;
+        if self.is_plain() {
+            self.check_crc(crc32fast::hash(self.contents))?;
 
-                Ok(Cow::Owned(out))
-            }
-            method => todo!("unimplemented compression method {:?}", method),
+            return Ok(Cow::Borrowed(self.contents));
         }
+
+        let mut out = Vec::with_capacity(self.initial_capacity());
+        self.write_with_limit(&mut out, limit)?;
+
+        Ok(Cow::Owned(out))
     }
 
     /// Decompress full contents into memory
@@ -205,10 +633,55 @@ impl<'a> CompressedZipFile<'a> {
     /// This method uses the default limit of 8 gigabytes. See
     /// [CompressedZipFile::decompressed_contents_with_limit] to configure this
     /// limit.
-    pub fn decompressed_contents(&self) -> Result<Cow<[u8]>, ZipParseError> {
+    pub fn decompressed_contents(&self) -> Result<Cow<'_, [u8]>, ZipParseError> {
         self.decompressed_contents_with_limit(Some(8 * GB))
     }
 
+    /// Decrypt and decompress full contents into memory, failing with
+    /// [`ZipParseError::InvalidPassword`] if `password` is wrong.
+    ///
+    /// Both traditional PKWARE encryption (ZipCrypto) and, with the `aes`
+    /// feature, WinZip AES are supported.
+    ///
+    /// The ZipCrypto password check is a single byte, so about 1 in 256 wrong
+    /// passwords get past it, and are only caught by the CRC-32 check once the
+    /// contents have been decrypted. WinZip AES checks a two byte password
+    /// verifier instead, and its HMAC-SHA1 authentication code rejects wrong
+    /// passwords and tampered contents alike once they have all been read.
+    ///
+    /// This method uses the default limit of 8 gigabytes.
+    pub fn decompressed_contents_with_password(
+        &self,
+        password: &[u8],
+    ) -> Result<Cow<'_, [u8]>, ZipParseError> {
+        self.check_limit(Some(8 * GB))?;
+
+        let mut out = Vec::with_capacity(self.initial_capacity());
+        self.write_with_password(&mut out, password)?;
+
+        Ok(Cow::Owned(out))
+    }
+
+    /// Efficiently writes decrypted and decompressed contents to sink without
+    /// loading full decompressed contents into memory. See
+    /// [CompressedZipFile::decompressed_contents_with_password].
+    ///
+    /// This method uses the default limit of 8 gigabytes.
+    pub fn write_with_password(
+        &self,
+        w: &mut dyn Write,
+        password: &[u8],
+    ) -> Result<(), ZipParseError> {
+        let file = CompressedZipFile {
+            metadata: self.metadata.clone(),
+            contents: self.contents,
+            verify_crc: self.verify_crc,
+            decoder_options: self.decoder_options.clone(),
+        };
+
+        file.password(password).write(w)
+    }
+
     /// This file's `Path` inside the ZIP archive.
     ///
     /// Note that this path may reference file paths outside the archive through
@@ -217,7 +690,7 @@ impl<'a> CompressedZipFile<'a> {
     /// file is untrusted.
     #[cfg(target_family = "unix")]
     pub fn file_path(&self) -> &Path {
-        &Path::new(OsStr::from_bytes(self.metadata.name))
+        Path::new(OsStr::from_bytes(self.metadata.name))
     }
 
     /// The raw bytes of this file's path inside the ZIP archive.
@@ -230,12 +703,24 @@ impl<'a> CompressedZipFile<'a> {
         self.metadata.name
     }
 
+    /// This file's path inside the ZIP archive, decoded as text. See
+    /// [`Metadata::file_name`].
+    pub fn file_name(&self) -> Cow<'a, str> {
+        self.metadata.file_name()
+    }
+
     /// The algorithm used to compress this file.
     ///
     /// This is typically [`CompressionMethodName::None`] or
-    /// [`CompressionMethodName::Deflate`].
+    /// [`CompressionMethodName::Deflate`]. Bzip2, LZMA, PPMd, Zstandard and XZ
+    /// require the `bzip2`, `lzma`, `ppmd`, `zstd` and `xz` features
+    /// respectively. Other methods can be decompressed with a
+    /// [`DecoderRegistry`].
+    ///
+    /// For files encrypted with WinZip AES, this is the real method from their
+    /// extra field. See [`Metadata::real_compression_method`].
     pub fn compression_method(&self) -> CompressionMethod {
-        self.metadata.compression_method
+        self.metadata.real_compression_method()
     }
 }
 
@@ -247,18 +732,723 @@ pub struct CentralDirectory<'a> {
 
 #[cfg(test)]
 mod test {
-    use crate::{ZipArchive, MB};
+    use std::{
+        io::{Cursor, Read, Write},
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{
+        CompressionMethod, DateTimeModified, DecoderRegistry, ExtractOptions, ExtractionLimits,
+        FileOptions, Metadata, ZipArchive, ZipFlags, ZipParseError, ZipReader, ZipWriter,
+        CENTRAL_DIRECTORY_FILE_SIGNATURE, END_CENTRAL_DIRECTORY_SIGNATURE, GB,
+        LOCAL_FILE_SIGNATURE, MB, ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE,
+        ZIP64_END_CENTRAL_DIRECTORY_SIGNATURE,
+    };
+
+    /// A single stored file where every size, offset and count is saturated
+    /// and only available through zip64 records
+    fn zip64_archive(contents: &[u8]) -> Vec<u8> {
+        let len = contents.len() as u64;
+        let crc = crc32fast::hash(contents);
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(&LOCAL_FILE_SIGNATURE);
+        buf.extend_from_slice(&45u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&20u16.to_le_bytes());
+        buf.push(b'a');
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&16u16.to_le_bytes());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(contents);
+
+        let central_dir_offset = buf.len() as u64;
+
+        buf.extend_from_slice(&CENTRAL_DIRECTORY_FILE_SIGNATURE);
+        buf.extend_from_slice(&45u16.to_le_bytes());
+        buf.extend_from_slice(&45u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&28u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.push(b'a');
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&24u16.to_le_bytes());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&0u64.to_le_bytes());
+
+        let zip64_end_offset = buf.len() as u64;
+        let central_dir_size = zip64_end_offset - central_dir_offset;
+
+        buf.extend_from_slice(&ZIP64_END_CENTRAL_DIRECTORY_SIGNATURE);
+        buf.extend_from_slice(&44u64.to_le_bytes());
+        buf.extend_from_slice(&45u16.to_le_bytes());
+        buf.extend_from_slice(&45u16.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        buf.extend_from_slice(&1u64.to_le_bytes());
+        buf.extend_from_slice(&central_dir_size.to_le_bytes());
+        buf.extend_from_slice(&central_dir_offset.to_le_bytes());
+
+        buf.extend_from_slice(&ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE);
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&zip64_end_offset.to_le_bytes());
+        buf.extend_from_slice(&1u32.to_le_bytes());
+
+        buf.extend_from_slice(&END_CENTRAL_DIRECTORY_SIGNATURE);
+        buf.extend_from_slice(&u16::MAX.to_le_bytes());
+        buf.extend_from_slice(&u16::MAX.to_le_bytes());
+        buf.extend_from_slice(&u16::MAX.to_le_bytes());
+        buf.extend_from_slice(&u16::MAX.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.extend_from_slice(&u32::MAX.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+
+        buf
+    }
+
+    #[test]
+    fn zip64() {
+        let archive = ZipArchive::from_buffer(zip64_archive(b"hello")).unwrap();
+
+        let central_directory = archive.central_directory();
+        assert!(central_directory.end.is_zip64);
+        assert_eq!(central_directory.end.total_entries, 1);
+
+        let header = &central_directory.files[0];
+        assert_eq!(header.local_header_offset, 0);
+        assert_eq!(header.metadata.compressed_size, 5);
+        assert_eq!(header.metadata.uncompressed_size, 5);
+
+        let file = archive.files().next().unwrap().unwrap();
+        assert_eq!(file.decompressed_contents().unwrap(), &b"hello"[..]);
+
+        let mut reader = ZipReader::new(Cursor::new(zip64_archive(b"hello"))).unwrap();
+        let mut contents = Vec::new();
+
+        assert!(reader.central_directory().end.is_zip64);
+        reader
+            .by_index(0)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"hello");
+    }
+
+    #[test]
+    fn out_of_bounds_offsets() {
+        // a zip64 locator pointing at `u64::MAX`, followed by an end of central
+        // directory record deferring every field to the zip64 record
+        let mut buffer = ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE.to_vec();
+        buffer.extend_from_slice(&0u32.to_le_bytes());
+        buffer.extend_from_slice(&u64::MAX.to_le_bytes());
+        buffer.extend_from_slice(&1u32.to_le_bytes());
+        buffer.extend_from_slice(&END_CENTRAL_DIRECTORY_SIGNATURE);
+        buffer.extend_from_slice(&[0xff; 8]);
+        buffer.extend_from_slice(&[0xff; 8]);
+        buffer.extend_from_slice(&0u16.to_le_bytes());
+        assert_eq!(buffer.len(), 42);
+
+        assert!(matches!(
+            ZipArchive::from_buffer(buffer),
+            Err(ZipParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn limit_before_allocating() {
+        let mut buffer = zip64_archive(b"hello");
+
+        // claim 16 gigabytes in the zip64 extra field of the central directory
+        let central = memchr::memmem::find(&buffer, &CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+        buffer[central + 51..central + 59].copy_from_slice(&(16 * GB as u64).to_le_bytes());
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let file = archive.by_index(0).unwrap();
+
+        assert!(matches!(
+            file.decompressed_contents_with_password(b"secret"),
+            Err(ZipParseError::FileTooLarge(size)) if size == 16 * GB as u64
+        ));
+    }
+
+    #[test]
+    fn untrusted_capacity() {
+        let mut buffer = zip64_archive(b"hello");
+
+        let central = memchr::memmem::find(&buffer, &CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+        buffer[central + 51..central + 59].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let file = archive.by_index(0).unwrap();
+
+        assert!(file.decompressed_contents_with_limit(None).is_err());
+    }
+
+    #[test]
+    fn extra_field_timestamps() {
+        let mut metadata = Metadata {
+            version_needed: 20,
+            compression_method: CompressionMethod::STORED,
+            date_time_modified: DateTimeModified::default(),
+            flags: ZipFlags(0),
+            name: b"a",
+            extra_field: &[],
+            compressed_size: 0,
+            uncompressed_size: 0,
+            crc: 0,
+        };
+
+        assert_eq!(
+            metadata.modified(),
+            DateTimeModified::default().to_system_time()
+        );
+        assert_eq!(metadata.accessed(), None);
+
+        // extended timestamp with modification and access times
+        let extended = [
+            0x55, 0x54, 9, 0, 0b011, 0x00, 0xca, 0x9a, 0x3b, 0x01, 0xca, 0x9a, 0x3b,
+        ];
+        metadata.extra_field = &extended;
+
+        assert_eq!(
+            metadata.modified(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+        );
+        assert_eq!(
+            metadata.accessed(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_000_000_001))
+        );
+        assert_eq!(metadata.created(), None);
+
+        // NTFS timestamps take precedence
+        let mut ntfs = vec![0x0a, 0x00, 32, 0, 0, 0, 0, 0, 1, 0, 24, 0];
+        let ticks = (1_000_000_000 + 11_644_473_600) * 10_000_000 + 5u64;
+        for _ in 0..3 {
+            ntfs.extend_from_slice(&ticks.to_le_bytes());
+        }
+        ntfs.extend_from_slice(&extended);
+        metadata.extra_field = &ntfs;
+
+        assert_eq!(
+            metadata.created(),
+            Some(UNIX_EPOCH + Duration::new(1_000_000_000, 500))
+        );
+    }
+
+    #[test]
+    fn file_name_encoding() {
+        let mut metadata = Metadata {
+            version_needed: 20,
+            compression_method: CompressionMethod::STORED,
+            date_time_modified: DateTimeModified::default(),
+            flags: ZipFlags(0),
+            name: b"caf\x82 \xb0.txt",
+            extra_field: &[],
+            compressed_size: 0,
+            uncompressed_size: 0,
+            crc: 0,
+        };
+
+        assert_eq!(metadata.file_name(), "café ░.txt");
+
+        // an up-to-date unicode path takes precedence over cp437
+        let mut unicode_path = vec![0x75, 0x70, 11, 0, 1];
+        unicode_path.extend_from_slice(&crc32fast::hash(metadata.name).to_le_bytes());
+        unicode_path.extend_from_slice("名前".as_bytes());
+        metadata.extra_field = &unicode_path;
+
+        assert_eq!(metadata.file_name(), "名前");
+
+        metadata.name = b"renamed";
+        assert_eq!(metadata.file_name(), "renamed");
+
+        metadata.name = "名前".as_bytes();
+        metadata.flags = ZipFlags(ZipFlags::LANGUAGE_ENCODING);
+        metadata.extra_field = &[];
+        assert_eq!(metadata.file_name(), "名前");
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        for i in 0..16 {
+            writer
+                .start_file(i.to_string(), FileOptions::default())
+                .unwrap();
+            writer
+                .write_all(i.to_string().repeat(100).as_bytes())
+                .unwrap();
+        }
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let archive = Arc::new(ZipArchive::from_buffer(buffer).unwrap());
+
+        let threads = (0..4)
+            .map(|_| {
+                let archive = Arc::clone(&archive);
+
+                std::thread::spawn(move || {
+                    for i in 0..16 {
+                        let file = archive.by_name(i.to_string().as_bytes()).unwrap();
+                        assert_eq!(
+                            file.decompressed_contents().unwrap(),
+                            i.to_string().repeat(100).as_bytes()
+                        );
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn owned_files() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        writer.start_file("a", FileOptions::default()).unwrap();
+        writer.write_all(b"a contents").unwrap();
+
+        let buffer: Arc<[u8]> = writer.finish().unwrap().into_inner().into();
+        let archive = Arc::new(ZipArchive::from_buffer(buffer).unwrap());
+
+        let file = archive.by_name_owned(b"a").unwrap();
+        assert!(archive.by_index_owned(1).is_err());
+
+        // the file keeps the archive alive
+        drop(archive);
+
+        let contents = std::thread::spawn(move || {
+            assert_eq!(file.file().metadata.name, b"a");
+            file.file().decompressed_contents().unwrap().into_owned()
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(contents, b"a contents");
+    }
+
+    #[test]
+    fn by_name() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        for (name, contents) in [("a", "first a"), ("dir/b", "b"), ("a", "second a")] {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        // the last of duplicate names wins
+        assert_eq!(archive.index_of(b"a"), Some(2));
+        assert_eq!(archive.index_of(b"dir/b"), Some(1));
+        assert_eq!(archive.index_of(b"dir"), None);
+
+        let file = archive.by_name(b"dir/b").unwrap();
+        assert_eq!(file.decompressed_contents().unwrap(), &b"b"[..]);
+
+        let file = archive.by_name(b"a").unwrap();
+        assert_eq!(file.decompressed_contents().unwrap(), &b"second a"[..]);
+
+        let file = archive.by_index(0).unwrap();
+        assert_eq!(file.decompressed_contents().unwrap(), &b"first a"[..]);
+
+        assert!(matches!(
+            archive.by_name(b"missing"),
+            Err(ZipParseError::FileNotFound(name)) if name == "missing"
+        ));
+        assert!(archive.by_index(3).is_err());
+    }
+
+    #[test]
+    fn size_limits() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.start_file("a", FileOptions::default()).unwrap();
+        writer.write_all(&[b'a'; 1000]).unwrap();
+
+        let mut buffer = writer.finish().unwrap().into_inner();
+
+        let archive = ZipArchive::from_buffer(buffer.as_slice()).unwrap();
+        let file = archive.by_index(0).unwrap();
+
+        assert_eq!(
+            file.decompressed_contents_with_limit(None).unwrap().len(),
+            1000
+        );
+        assert!(file.decompressed_contents_with_limit(Some(1000)).is_ok());
+        assert!(matches!(
+            file.decompressed_contents_with_limit(Some(999)),
+            Err(ZipParseError::FileTooLarge(1000))
+        ));
+
+        // claim the file is smaller than it is, which stops the decoder as
+        // soon as it produces more
+        let central = memchr::memmem::rfind(&buffer, &CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+        buffer[central + 24..central + 28].copy_from_slice(&10u32.to_le_bytes());
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let file = archive.by_index(0).unwrap();
+
+        let mut out = Vec::new();
+        assert!(matches!(
+            file.write_with_limit(&mut out, None),
+            Err(ZipParseError::IoError(err)) if err.kind() == std::io::ErrorKind::InvalidData
+        ));
+        assert!(out.len() <= 10);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        writer
+            .start_file(
+                "stored",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+        writer.write_all(b"stored contents").unwrap();
+        writer
+            .start_file("deflated", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"deflated contents").unwrap();
+
+        let mut buffer = writer.finish().unwrap().into_inner();
+
+        // corrupt the stored contents and the crc of the deflated file
+        let stored = memchr::memmem::find(&buffer, b"stored contents").unwrap();
+        buffer[stored] = b'S';
+        let central = memchr::memmem::rfind(&buffer, &CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+        buffer[central + 16] ^= 0xff;
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+        for file in files {
+            assert!(matches!(
+                file.decompressed_contents(),
+                Err(ZipParseError::CrcMismatch { .. })
+            ));
+            assert!(matches!(
+                file.write(&mut Vec::new()),
+                Err(ZipParseError::CrcMismatch { .. })
+            ));
+
+            let file = file.verify_crc(false);
+            assert!(file.decompressed_contents().is_ok());
+            assert!(file.write(&mut Vec::new()).is_ok());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "bzip2")]
+    fn bzip2() {
+        let archive = ZipArchive::from_path("files/bzip2.zip").unwrap();
+        let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(files[0].compression_method(), CompressionMethod(12));
+        assert_eq!(
+            files[0].decompressed_contents().unwrap(),
+            b"hello world\n".repeat(100)
+        );
+        assert_eq!(files[1].decompressed_contents().unwrap(), &b""[..]);
+
+        let mut contents = Vec::new();
+        files[0].write(&mut contents).unwrap();
+        assert_eq!(contents, b"hello world\n".repeat(100));
+
+        let mut reader = ZipReader::from_path("files/bzip2.zip").unwrap();
+        contents.clear();
+        reader
+            .by_index(0)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"hello world\n".repeat(100));
+    }
+
+    #[test]
+    fn zipcrypto() {
+        let archive = ZipArchive::from_path("files/zipcrypto.zip").unwrap();
+        let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+        // both files use a data descriptor, so the password is checked against
+        // the modification time
+        for (file, expected) in files
+            .iter()
+            .zip([b"hello world\n".repeat(100), b"stored".to_vec()])
+        {
+            assert!(file.is_encrypted());
+            assert!(matches!(
+                file.decompressed_contents(),
+                Err(ZipParseError::PasswordRequired)
+            ));
+            assert!(matches!(
+                file.decompressed_contents_with_password(b"wrong"),
+                Err(ZipParseError::InvalidPassword)
+            ));
+            assert_eq!(
+                file.decompressed_contents_with_password(b"secret").unwrap(),
+                expected
+            );
+
+            let mut contents = Vec::new();
+            file.write_with_password(&mut contents, b"secret").unwrap();
+            assert_eq!(contents, expected);
+        }
+
+        let mut reader = ZipReader::from_path("files/zipcrypto.zip").unwrap();
+        reader.set_password(b"secret");
+
+        let mut contents = Vec::new();
+        reader
+            .by_index(1)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"stored");
+    }
+
+    #[test]
+    #[cfg(feature = "aes")]
+    fn winzip_aes() {
+        let archive = ZipArchive::from_path("files/aes.zip").unwrap();
+        let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+        // libarchive uses AE-1 for the larger file and AE-2 for the smaller
+        assert!(files[0].metadata.has_crc());
+        assert!(!files[1].metadata.has_crc());
+
+        for (file, expected) in files
+            .iter()
+            .zip([b"hello world\n".repeat(100), b"stored".to_vec()])
+        {
+            assert_eq!(file.compression_method(), CompressionMethod::DEFLATE);
+            assert!(matches!(
+                file.decompressed_contents(),
+                Err(ZipParseError::PasswordRequired)
+            ));
+            assert!(matches!(
+                file.decompressed_contents_with_password(b"wrong"),
+                Err(ZipParseError::InvalidPassword)
+            ));
+            assert_eq!(
+                file.decompressed_contents_with_password(b"secret").unwrap(),
+                expected
+            );
+        }
+
+        // tampering with the contents is caught by the authentication code,
+        // even though the CRC-32 isn't checked
+        let buffer = std::fs::read("files/aes.zip").unwrap();
+        let offset = files[1].compressed_contents().len() - 11;
+        let offset =
+            offset + memchr::memmem::find(&buffer, files[1].compressed_contents()).unwrap();
+
+        let mut buffer = buffer;
+        buffer[offset] ^= 1;
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let file = archive.files().nth(1).unwrap().unwrap();
+
+        assert!(matches!(
+            file.decompressed_contents_with_password(b"secret"),
+            Err(ZipParseError::IoError(err)) if err.kind() == std::io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd_frames() {
+        let contents = b"hello world\n".repeat(100);
+
+        // as written by pzstd, which compresses chunks into separate frames
+        let mut frames = zstd::encode_all(&contents[..600], 0).unwrap();
+        frames.extend(zstd::encode_all(&contents[600..], 0).unwrap());
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer
+            .start_file(
+                "a",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+        writer.write_all(&frames).unwrap();
+
+        // relabel the frames as zstd compressed contents
+        let mut buffer = writer.finish().unwrap().into_inner();
+        let central = memchr::memmem::find(&buffer, &CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+        buffer[8..10].copy_from_slice(&CompressionMethod::ZSTD.0.to_le_bytes());
+        buffer[central + 10..central + 12]
+            .copy_from_slice(&CompressionMethod::ZSTD.0.to_le_bytes());
+        buffer[central + 16..central + 20]
+            .copy_from_slice(&crc32fast::hash(&contents).to_le_bytes());
+        buffer[central + 24..central + 28].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let file = archive.by_index(0).unwrap();
+
+        assert_eq!(file.compression_method(), CompressionMethod::ZSTD);
+        assert_eq!(file.decompressed_contents().unwrap(), contents);
+    }
+
+    #[test]
+    #[cfg(feature = "lzma")]
+    fn lzma() {
+        let mut buffer = std::fs::read("files/lzma.zip").unwrap();
+
+        for eos_marker in [true, false] {
+            let archive = ZipArchive::from_buffer(buffer.as_slice()).unwrap();
+            let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+            assert_eq!(files[0].compression_method(), CompressionMethod(14));
+            assert_eq!(
+                files[0].metadata.flags.0 & ZipFlags::COMPRESSION_OPTION1 != 0,
+                eos_marker
+            );
+            assert_eq!(
+                files[0].decompressed_contents().unwrap(),
+                b"hello world\n".repeat(100)
+            );
+            assert_eq!(files[1].decompressed_contents().unwrap(), &b""[..]);
+
+            let mut reader = ZipReader::new(Cursor::new(buffer.as_slice())).unwrap();
+            let mut contents = Vec::new();
+            reader
+                .by_index(0)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            assert_eq!(contents, b"hello world\n".repeat(100));
+
+            // without the end of stream marker flag, decoding stops after the
+            // uncompressed size
+            for signature in [LOCAL_FILE_SIGNATURE, CENTRAL_DIRECTORY_FILE_SIGNATURE] {
+                let flags = if signature == LOCAL_FILE_SIGNATURE {
+                    6
+                } else {
+                    8
+                };
+                let offsets = memchr::memmem::find_iter(&buffer, &signature).collect::<Vec<_>>();
+
+                for offset in offsets {
+                    buffer[offset + flags] &= !(ZipFlags::COMPRESSION_OPTION1 as u8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_compression() {
+        let contents = b"inverted contents";
+        let inverted = contents.iter().map(|byte| !byte).collect::<Vec<_>>();
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer
+            .start_file(
+                "inverted",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+        writer.write_all(&inverted).unwrap();
+
+        let mut buffer = writer.finish().unwrap().into_inner();
+
+        // relabel the file with a made up compression method, and give it the
+        // crc of its decompressed contents
+        let central = memchr::memmem::rfind(&buffer, &CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+        let crc = crc32fast::hash(contents).to_le_bytes();
+        buffer[8] = 0x7f;
+        buffer[14..18].copy_from_slice(&crc);
+        buffer[central + 10] = 0x7f;
+        buffer[central + 16..central + 20].copy_from_slice(&crc);
+
+        let method = CompressionMethod(0x7f);
+        assert!(!method.is_supported());
+        assert!(CompressionMethod::DEFLATE.is_supported());
+
+        let mut archive = ZipArchive::from_buffer(buffer.as_slice()).unwrap();
+        let file = archive.files().next().unwrap().unwrap();
+        assert!(matches!(
+            file.decompressed_contents(),
+            Err(ZipParseError::UnsupportedCompression(found)) if found == method
+        ));
+
+        let mut decoders = DecoderRegistry::new();
+        decoders.register(method, |_, mut reader| {
+            let mut decoded = Vec::new();
+            reader.read_to_end(&mut decoded)?;
+            decoded.iter_mut().for_each(|byte| *byte = !*byte);
+
+            Ok(Box::new(Cursor::new(decoded)))
+        });
+        assert!(decoders.contains(method));
+
+        archive.set_decoders(decoders.clone());
+        let file = archive.files().next().unwrap().unwrap();
+        assert_eq!(file.decompressed_contents().unwrap(), &contents[..]);
+
+        let mut reader = ZipReader::new(Cursor::new(buffer)).unwrap();
+        assert!(reader.by_index(0).is_err());
+
+        reader.set_decoders(decoders);
+        let mut decoded = Vec::new();
+        reader
+            .by_index(0)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, contents);
+    }
 
     #[test]
-    #[should_panic]
     fn zip_bomb() {
-        let mut bomb = ZipArchive::from_path("files/bomb.zip").unwrap();
+        let bomb = ZipArchive::from_path("files/bomb.zip").unwrap();
+
+        // each file is in the contents of the one before it
+        assert!(matches!(
+            bomb.check_limits(&ExtractionLimits::default()),
+            Err(ZipParseError::OverlappingFiles(_))
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            bomb.extract_to(dir.path(), ExtractOptions::default()),
+            Err(ZipParseError::OverlappingFiles(_))
+        ));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
 
         for file in bomb.files() {
             let file = file.unwrap();
 
-            file.decompressed_contents_with_limit(Some(20 * MB))
-                .unwrap();
+            assert!(matches!(
+                file.decompressed_contents_with_limit(Some(20 * MB)),
+                Err(ZipParseError::FileTooLarge(_))
+            ));
         }
     }
 }
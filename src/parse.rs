@@ -1,27 +1,30 @@
-use std::ops::Deref;
+use std::ops::Range;
 
 use crate::{
-    common::*, CentralDirectory, CentralDirectoryFileHeader, CompressedZipFile,
-    EndCentralDirectory, Metadata, ZipParseError,
+    common::*,
+    decompress::DecoderOptions,
+    extra::{find_extra_field, ExtraField},
+    CentralDirectory, CentralDirectoryFileHeader, CompressedZipFile, EndCentralDirectory, Metadata,
+    ZipParseError,
 };
 use memchr::memmem;
 
 #[derive(Debug)]
-pub(super) struct Parser<B: Deref<Target = [u8]>> {
-    buffer: B,
+pub(super) struct Parser<'a> {
+    buffer: &'a [u8],
     cursor: usize,
 }
 
-impl<'a, B: Deref<Target = [u8]>> Parser<B> {
-    pub fn new(buffer: B) -> Self {
+impl<'a> Parser<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
         Self { buffer, cursor: 0 }
     }
 
     fn read_u8_optional(&mut self) -> Option<u8> {
-        self.buffer.get(self.cursor).copied().map(|b| {
-            self.cursor += 1;
-            b
-        })
+        self.buffer
+            .get(self.cursor)
+            .copied()
+            .inspect(|_| self.cursor += 1)
     }
 
     fn read_u32_optional(&mut self) -> Option<u32> {
@@ -33,25 +36,22 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
         Some(u32::from_le_bytes([b1, b2, b3, b4]))
     }
 
-    fn read_u8(&mut self) -> Result<u8, ZipParseError> {
+    pub(crate) fn read_u8(&mut self) -> Result<u8, ZipParseError> {
         self.buffer
             .get(self.cursor)
             .copied()
-            .map(|b| {
-                self.cursor += 1;
-                b
-            })
+            .inspect(|_| self.cursor += 1)
             .ok_or(ZipParseError::UnexpectedEof)
     }
 
-    fn read_u16(&mut self) -> Result<u16, ZipParseError> {
+    pub(crate) fn read_u16(&mut self) -> Result<u16, ZipParseError> {
         let b1 = self.read_u8()?;
         let b2 = self.read_u8()?;
 
         Ok(u16::from_le_bytes([b1, b2]))
     }
 
-    fn read_u32(&mut self) -> Result<u32, ZipParseError> {
+    pub(crate) fn read_u32(&mut self) -> Result<u32, ZipParseError> {
         let b1 = self.read_u8()?;
         let b2 = self.read_u8()?;
         let b3 = self.read_u8()?;
@@ -60,17 +60,20 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
         Ok(u32::from_le_bytes([b1, b2, b3, b4]))
     }
 
-    fn read_signature(&mut self, signature: [u8; 4]) -> bool {
-        if self.buffer.len() <= self.cursor + 3 {
-            return false;
-        }
+    pub(crate) fn read_u64(&mut self) -> Result<u64, ZipParseError> {
+        let lo = self.read_u32()?;
+        let hi = self.read_u32()?;
 
-        let b1 = self.buffer[self.cursor];
-        let b2 = self.buffer[self.cursor + 1];
-        let b3 = self.buffer[self.cursor + 2];
-        let b4 = self.buffer[self.cursor + 3];
+        Ok(u64::from(lo) | (u64::from(hi) << 32))
+    }
 
-        if [b1, b2, b3, b4] == signature {
+    /// The four bytes at the cursor, if there are that many left
+    fn peek_signature(&self) -> Option<[u8; 4]> {
+        self.buffer.get(self.cursor..)?.get(..4)?.try_into().ok()
+    }
+
+    fn read_signature(&mut self, signature: [u8; 4]) -> bool {
+        if self.peek_signature() == Some(signature) {
             self.read_u32_optional();
             true
         } else {
@@ -79,16 +82,9 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
     }
 
     fn expect_signature(&mut self, expected: [u8; 4]) -> Result<(), ZipParseError> {
-        if self.buffer.len() <= self.cursor + 3 {
-            return Err(ZipParseError::Generic("expected 4 byte signature"));
-        }
-
-        let b1 = self.buffer[self.cursor];
-        let b2 = self.buffer[self.cursor + 1];
-        let b3 = self.buffer[self.cursor + 2];
-        let b4 = self.buffer[self.cursor + 3];
-
-        let found = [b1, b2, b3, b4];
+        let found = self
+            .peek_signature()
+            .ok_or(ZipParseError::Generic("expected 4 byte signature"))?;
 
         if found == expected {
             self.read_u32_optional();
@@ -98,47 +94,40 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
         }
     }
 
-    fn get_byte_range(&mut self, len: usize) -> Result<&'a [u8], ZipParseError> {
+    /// Convert an offset read from the archive, which can't be trusted to lie
+    /// within the buffer
+    fn offset(&self, offset: u64) -> Result<usize, ZipParseError> {
+        usize::try_from(offset)
+            .ok()
+            .filter(|&offset| offset <= self.buffer.len())
+            .ok_or(ZipParseError::UnexpectedEof)
+    }
+
+    pub(crate) fn get_byte_range(&mut self, len: usize) -> Result<&'a [u8], ZipParseError> {
         let start = self.cursor;
 
-        self.cursor += len;
+        self.cursor = self.cursor.saturating_add(len);
 
-        unsafe { self.lengthen_buffer_lifetime() }
+        self.buffer
             .get(start..self.cursor)
             .ok_or(ZipParseError::UnexpectedEof)
     }
 
-    unsafe fn lengthen_buffer_lifetime(&self) -> &'a [u8] {
-        &*(&*self.buffer as *const _)
-    }
-
-    fn read_metadata(&mut self) -> Result<Metadata<'a>, ZipParseError> {
+    pub(crate) fn read_metadata(&mut self) -> Result<Metadata<'a>, ZipParseError> {
         let version_needed = self.read_u16()?;
         let flags = ZipFlags(self.read_u16()?);
         let compression_method = CompressionMethod(self.read_u16()?);
         let last_mod_date_time = DateTimeModified::from_u32(self.read_u32()?);
-        let mut crc = self.read_u32()?;
-        let mut compressed_size = u64::from(self.read_u32()?);
-        let mut uncompressed_size = u64::from(self.read_u32()?);
+        let crc = self.read_u32()?;
+        let compressed_size = u64::from(self.read_u32()?);
+        let uncompressed_size = u64::from(self.read_u32()?);
         let file_name_len = usize::from(self.read_u16()?);
         let extra_field_len = usize::from(self.read_u16()?);
 
         let file_name = self.get_byte_range(file_name_len)?;
         let extra_field = self.get_byte_range(extra_field_len)?;
 
-        if flags.has_data_descriptor() {
-            let optional_signature = self.read_u32()?;
-            crc = if optional_signature.to_be_bytes() == DATA_DESCRIPTOR_SIGNATURE {
-                self.read_u32()?
-            } else {
-                optional_signature
-            };
-
-            compressed_size = u64::from(self.read_u32()?);
-            uncompressed_size = u64::from(self.read_u32()?);
-        }
-
-        Ok(Metadata {
+        let mut metadata = Metadata {
             version_needed,
             compression_method,
             date_time_modified: last_mod_date_time,
@@ -148,10 +137,14 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
             crc,
             compressed_size,
             uncompressed_size,
-        })
+        };
+
+        apply_zip64_extra_field(&mut metadata, None, None)?;
+
+        Ok(metadata)
     }
 
-    fn read_central_directory_file_headers(
+    pub(crate) fn read_central_directory_file_headers(
         &mut self,
         offset: usize,
     ) -> Result<Vec<CentralDirectoryFileHeader<'a>>, ZipParseError> {
@@ -160,8 +153,10 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
         let mut headers = Vec::new();
 
         while self.read_signature(CENTRAL_DIRECTORY_FILE_SIGNATURE) {
-            let os = Os(self.read_u8()?);
+            // "version made by" is a little-endian u16 with the spec version in
+            // the low byte and the host os in the high byte
             let zip_specification_version = self.read_u8()?;
+            let os = Os(self.read_u8()?);
             let version_needed = self.read_u16()?;
             let bit_flags = ZipFlags(self.read_u16()?);
             let compression_method = CompressionMethod(self.read_u16()?);
@@ -172,16 +167,16 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
             let file_name_len = usize::from(self.read_u16()?);
             let extra_field_len = usize::from(self.read_u16()?);
             let comment_len = usize::from(self.read_u16()?);
-            let disk_num_start = self.read_u16()?;
+            let mut disk_num_start = u32::from(self.read_u16()?);
             let internal_attributes = InternalAttributes(self.read_u16()?);
             let external_attributes = ExternalAttributes(self.read_u32()?);
-            let local_header_offset = self.read_u32()?;
+            let mut local_header_offset = u64::from(self.read_u32()?);
 
             let file_name = self.get_byte_range(file_name_len)?;
             let extra_field = self.get_byte_range(extra_field_len)?;
             let comment = self.get_byte_range(comment_len)?;
 
-            let metadata = Metadata {
+            let mut metadata = Metadata {
                 version_needed,
                 compression_method,
                 date_time_modified,
@@ -193,6 +188,12 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
                 uncompressed_size,
             };
 
+            apply_zip64_extra_field(
+                &mut metadata,
+                Some(&mut local_header_offset),
+                Some(&mut disk_num_start),
+            )?;
+
             headers.push(CentralDirectoryFileHeader {
                 os,
                 metadata,
@@ -208,7 +209,9 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
         Ok(headers)
     }
 
-    fn read_end_central_directory(
+    /// Read the classic end of central directory record, without consulting
+    /// any zip64 records
+    pub(crate) fn read_end_central_directory(
         &mut self,
         offset: usize,
     ) -> Result<EndCentralDirectory, ZipParseError> {
@@ -228,44 +231,249 @@ impl<'a, B: Deref<Target = [u8]>> Parser<B> {
         self.cursor += usize::from(comment_len);
 
         Ok(EndCentralDirectory {
-            disk_num,
-            disk_central_dir_num,
-            disk_entries,
-            total_entries,
-            central_dir_size,
-            central_dir_offset,
+            disk_num: u32::from(disk_num),
+            disk_central_dir_num: u32::from(disk_central_dir_num),
+            disk_entries: u64::from(disk_entries),
+            total_entries: u64::from(total_entries),
+            central_dir_size: u64::from(central_dir_size),
+            central_dir_offset: u64::from(central_dir_offset),
+            is_zip64: false,
         })
     }
 
+    /// Returns the offset of the zip64 end of central directory record if a
+    /// zip64 locator immediately precedes the classic record at `offset`
+    pub(crate) fn read_zip64_locator(
+        &mut self,
+        offset: usize,
+    ) -> Result<Option<u64>, ZipParseError> {
+        let locator_offset = match offset.checked_sub(ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_LEN) {
+            Some(locator_offset) => locator_offset,
+            None => return Ok(None),
+        };
+
+        self.cursor = locator_offset;
+
+        if !self.read_signature(ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE) {
+            return Ok(None);
+        }
+
+        let _disk_with_zip64_end = self.read_u32()?;
+        let zip64_end_offset = self.read_u64()?;
+        let _total_disks = self.read_u32()?;
+
+        Ok(Some(zip64_end_offset))
+    }
+
+    pub(crate) fn read_zip64_end_central_directory(
+        &mut self,
+        offset: usize,
+        end: &mut EndCentralDirectory,
+    ) -> Result<(), ZipParseError> {
+        self.cursor = offset;
+
+        self.expect_signature(ZIP64_END_CENTRAL_DIRECTORY_SIGNATURE)?;
+
+        let _record_size = self.read_u64()?;
+        let _version_made_by = self.read_u16()?;
+        let _version_needed = self.read_u16()?;
+
+        end.disk_num = self.read_u32()?;
+        end.disk_central_dir_num = self.read_u32()?;
+        end.disk_entries = self.read_u64()?;
+        end.total_entries = self.read_u64()?;
+        end.central_dir_size = self.read_u64()?;
+        end.central_dir_offset = self.read_u64()?;
+        end.is_zip64 = true;
+
+        Ok(())
+    }
+
     pub(super) fn parse_central_directory(
         &mut self,
     ) -> Result<CentralDirectory<'a>, ZipParseError> {
-        // todo: perhaps we need to not select the first one
-        for offset in memmem::rfind_iter(&self.buffer, &END_CENTRAL_DIRECTORY_SIGNATURE) {
-            let end = self.read_end_central_directory(offset)?;
-            let file_headers =
-                self.read_central_directory_file_headers(end.central_dir_offset as usize)?;
-
-            return Ok(CentralDirectory {
-                files: file_headers,
-                end,
-            });
+        let offset = find_end_central_directory(self.buffer)
+            .ok_or(ZipParseError::MissingCentralDirectory)?;
+
+        let mut end = self.read_end_central_directory(offset)?;
+
+        if let Some(zip64_end_offset) = self.read_zip64_locator(offset)? {
+            let zip64_end_offset = self.offset(zip64_end_offset)?;
+            self.read_zip64_end_central_directory(zip64_end_offset, &mut end)?;
         }
 
-        Err(ZipParseError::MissingCentralDirectory)
+        let central_dir_offset = self.offset(end.central_dir_offset)?;
+        let file_headers = self.read_central_directory_file_headers(central_dir_offset)?;
+
+        Ok(CentralDirectory {
+            files: file_headers,
+            end,
+        })
     }
 
     pub(super) fn read_file(
         &mut self,
         central_directory_header: &CentralDirectoryFileHeader,
     ) -> Result<CompressedZipFile<'a>, ZipParseError> {
-        self.cursor = central_directory_header.local_header_offset as usize;
+        self.cursor = self.offset(central_directory_header.local_header_offset)?;
 
         self.expect_signature(LOCAL_FILE_SIGNATURE)?;
 
-        let metadata = self.read_metadata()?;
-        let contents = self.get_byte_range(metadata.compressed_size as usize)?;
+        let mut metadata = self.read_metadata()?;
+
+        // the local header may leave these zeroed out (when a data descriptor
+        // follows the contents) or masked (when they only fit in zip64 fields),
+        // so we defer to the central directory, which is always authoritative
+        metadata.crc = central_directory_header.metadata.crc;
+        metadata.compressed_size = central_directory_header.metadata.compressed_size;
+        metadata.uncompressed_size = central_directory_header.metadata.uncompressed_size;
+
+        let compressed_size =
+            usize::try_from(metadata.compressed_size).map_err(|_| ZipParseError::UnexpectedEof)?;
+        let contents = self.get_byte_range(compressed_size)?;
+
+        Ok(CompressedZipFile {
+            metadata,
+            contents,
+            verify_crc: true,
+            decoder_options: DecoderOptions::default(),
+        })
+    }
+}
+
+/// A parsed central directory which records where its names, extra fields
+/// and comments lie in the buffer rather than borrowing them, so that it can be
+/// stored alongside the buffer that owns them
+#[derive(Debug)]
+pub(crate) struct StoredCentralDirectory {
+    files: Vec<StoredFileHeader>,
+    end: EndCentralDirectory,
+}
+
+#[derive(Debug)]
+struct StoredFileHeader {
+    /// The header, with its borrowed fields left empty
+    header: CentralDirectoryFileHeader<'static>,
+    name: Range<usize>,
+    extra_field: Range<usize>,
+    comment: Range<usize>,
+}
+
+impl StoredCentralDirectory {
+    /// Store `central_directory`, which must have been parsed from `buffer`
+    pub(crate) fn new(buffer: &[u8], central_directory: CentralDirectory) -> Self {
+        let files = central_directory
+            .files
+            .into_iter()
+            .map(|header| StoredFileHeader {
+                name: range_within(buffer, header.metadata.name),
+                extra_field: range_within(buffer, header.metadata.extra_field),
+                comment: range_within(buffer, header.comment),
+                header: CentralDirectoryFileHeader {
+                    metadata: Metadata {
+                        name: &[],
+                        extra_field: &[],
+                        ..header.metadata
+                    },
+                    comment: &[],
+                    ..header
+                },
+            })
+            .collect();
+
+        Self {
+            files,
+            end: central_directory.end,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The header at `index`, borrowing from `buffer`, which must be the one
+    /// this was created with
+    pub(crate) fn header<'b>(
+        &self,
+        buffer: &'b [u8],
+        index: usize,
+    ) -> Option<CentralDirectoryFileHeader<'b>> {
+        let stored = self.files.get(index)?;
+
+        Some(CentralDirectoryFileHeader {
+            metadata: Metadata {
+                name: &buffer[stored.name.clone()],
+                extra_field: &buffer[stored.extra_field.clone()],
+                ..stored.header.metadata.clone()
+            },
+            comment: &buffer[stored.comment.clone()],
+            ..stored.header.clone()
+        })
+    }
+
+    /// The whole central directory, borrowing from `buffer`, which must be the
+    /// one this was created with
+    pub(crate) fn load<'b>(&self, buffer: &'b [u8]) -> CentralDirectory<'b> {
+        CentralDirectory {
+            files: (0..self.len())
+                .filter_map(|index| self.header(buffer, index))
+                .collect(),
+            end: self.end.clone(),
+        }
+    }
+}
+
+/// The position of `slice` in `buffer`, which it must have been taken from
+pub(crate) fn range_within(buffer: &[u8], slice: &[u8]) -> Range<usize> {
+    let start = slice.as_ptr() as usize - buffer.as_ptr() as usize;
+    debug_assert!(start + slice.len() <= buffer.len());
+
+    start..start + slice.len()
+}
+
+/// Size of the zip64 end of central directory locator, including its signature
+pub(crate) const ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_LEN: usize = 20;
+
+/// Size of the zip64 end of central directory record, including its signature
+/// but excluding any extensible data
+pub(crate) const ZIP64_END_CENTRAL_DIRECTORY_LEN: usize = 56;
 
-        Ok(CompressedZipFile { metadata, contents })
+/// Size of the end of central directory record, including its signature but
+/// excluding the comment
+pub(crate) const END_CENTRAL_DIRECTORY_LEN: usize = 22;
+
+/// Size of the local file header, including its signature but excluding the
+/// name and extra field
+pub(crate) const LOCAL_FILE_HEADER_LEN: usize = 30;
+
+/// Find the offset of the end of central directory record, which is the last
+/// thing in the archive aside from the archive comment
+pub(crate) fn find_end_central_directory(buffer: &[u8]) -> Option<usize> {
+    // todo: perhaps we need to not select the first one
+    memmem::rfind_iter(buffer, &END_CENTRAL_DIRECTORY_SIGNATURE).next()
+}
+
+/// Replace any 32-bit fields saturated to `0xFFFFFFFF` (or `0xFFFF` for the
+/// disk number) with their real values from the zip64 extended information
+/// extra field.
+fn apply_zip64_extra_field(
+    metadata: &mut Metadata,
+    local_header_offset: Option<&mut u64>,
+    disk_num_start: Option<&mut u32>,
+) -> Result<(), ZipParseError> {
+    let field = match find_extra_field(metadata.extra_field, ZIP64_EXTRA_FIELD_ID) {
+        Some(data) => ExtraField::parse(ZIP64_EXTRA_FIELD_ID, data)?,
+        None => return Ok(()),
+    };
+
+    if let ExtraField::Zip64(zip64) = field {
+        zip64.resolve(
+            &mut metadata.uncompressed_size,
+            &mut metadata.compressed_size,
+            local_header_offset,
+            disk_num_start,
+        )?;
     }
+
+    Ok(())
 }
@@ -0,0 +1,222 @@
+//! A decoder for Reduce, which PKZIP 0.9 stored with compression methods 2
+//! through 5.
+//!
+//! Bytes are first compressed with a simple run length scheme, in which a
+//! `0x90` byte introduces a back reference. The result is then encoded using
+//! "follower sets": for each byte, a short list of the bytes most likely to
+//! come next, which can be referenced with only a few bits. The compression
+//! factor determines how many bits of a back reference go to its distance
+//! rather than its length.
+
+use std::io::{self, BufRead, Read};
+
+use crate::bits::{invalid_data, BitReader};
+
+/// Large enough for the farthest back reference, 4096 bytes at factor 4
+const WINDOW_SIZE: usize = 1 << 12;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+
+/// Introduces a back reference, or a literal `0x90` if followed by a zero
+const DLE: u8 = 0x90;
+
+const MAX_FOLLOWERS: usize = 32;
+
+/// Read the follower set of every byte, which are stored from the one for
+/// `0xff` down to the one for `0x00`
+fn read_follower_sets<R: BufRead>(bits: &mut BitReader<R>) -> io::Result<Vec<Vec<u8>>> {
+    let mut sets = vec![Vec::new(); 256];
+
+    for set in sets.iter_mut().rev() {
+        let len = bits.bits(6)? as usize;
+
+        if len > MAX_FOLLOWERS {
+            return Err(invalid_data("invalid follower set"));
+        }
+
+        for _ in 0..len {
+            set.push(bits.bits(8)? as u8);
+        }
+    }
+
+    Ok(sets)
+}
+
+/// Streaming Reduce decoder, which stops once `uncompressed_size` bytes have
+/// been produced
+#[derive(Debug)]
+pub(crate) struct ReduceDecoder<R> {
+    bits: BitReader<R>,
+    /// The compression factor, from 1 to 4
+    factor: u32,
+    /// The likely successors of each byte, read before the first output
+    follower_sets: Option<Vec<Vec<u8>>>,
+    /// The last byte decoded from the follower sets, which selects the set
+    /// used for the next one
+    previous: u8,
+    window: Vec<u8>,
+    written: u64,
+    /// A back reference which has not been fully copied out yet
+    copy_len: usize,
+    copy_distance: usize,
+    remaining: u64,
+}
+
+impl<R: BufRead> ReduceDecoder<R> {
+    pub(crate) fn new(reader: R, factor: u32, uncompressed_size: u64) -> Self {
+        debug_assert!((1..=4).contains(&factor));
+
+        Self {
+            bits: BitReader::new(reader),
+            factor,
+            follower_sets: None,
+            previous: 0,
+            window: vec![0; WINDOW_SIZE],
+            written: 0,
+            copy_len: 0,
+            copy_distance: 0,
+            remaining: uncompressed_size,
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<u8> {
+        let sets = match &mut self.follower_sets {
+            Some(sets) => sets,
+            sets @ None => sets.insert(read_follower_sets(&mut self.bits)?),
+        };
+        let followers = &sets[usize::from(self.previous)];
+
+        let byte = if followers.is_empty() || self.bits.bit()? {
+            self.bits.bits(8)? as u8
+        } else {
+            // enough bits to index the set, but always at least one
+            let index_len = (usize::BITS - (followers.len() - 1).leading_zeros()).max(1);
+            let index = self.bits.bits(index_len)? as usize;
+
+            *followers
+                .get(index)
+                .ok_or_else(|| invalid_data("invalid follower index"))?
+        };
+
+        self.previous = byte;
+
+        Ok(byte)
+    }
+
+    /// Read the remainder of a back reference, after its `DLE`
+    fn read_back_reference(&mut self, v: u8) -> io::Result<()> {
+        let len_bits = 8 - self.factor;
+        let len_mask = (1 << len_bits) - 1;
+
+        let mut len = usize::from(v) & len_mask;
+        if len == len_mask {
+            len += usize::from(self.next_byte()?);
+        }
+
+        let distance_high = usize::from(v) >> len_bits;
+        let distance_low = usize::from(self.next_byte()?);
+
+        self.copy_len = len + 3;
+        self.copy_distance = (distance_high << 8) + distance_low + 1;
+
+        Ok(())
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.window[self.written as usize & WINDOW_MASK] = byte;
+        self.written += 1;
+    }
+}
+
+impl<R: BufRead> Read for ReduceDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        while n < buf.len() && self.remaining > 0 {
+            let byte = if self.copy_len > 0 {
+                self.copy_len -= 1;
+
+                // references before the start of the file read as zeros,
+                // which the window is initialized to
+                self.window[(self.written as usize).wrapping_sub(self.copy_distance) & WINDOW_MASK]
+            } else {
+                match self.next_byte()? {
+                    DLE => match self.next_byte()? {
+                        0 => DLE,
+                        v => {
+                            self.read_back_reference(v)?;
+                            continue;
+                        }
+                    },
+                    byte => byte,
+                }
+            };
+
+            self.push(byte);
+            buf[n] = byte;
+            n += 1;
+            self.remaining -= 1;
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::bits::BitWriter;
+
+    use super::{ReduceDecoder, DLE};
+
+    #[test]
+    fn expand() {
+        let mut w = BitWriter::default();
+
+        // every follower set is empty except the one for 'a'
+        for byte in (0..=255).rev() {
+            if byte == u32::from(b'a') {
+                w.bits(2, 6);
+                w.bits(u32::from(b'b'), 8);
+                w.bits(u32::from(b'c'), 8);
+            } else {
+                w.bits(0, 6);
+            }
+        }
+
+        let symbols = [
+            b'a', b'b', b'c', b'a', b'b', // six bytes from three back
+            DLE, 3, 2, // a literal DLE
+            DLE, 0, // three bytes from before the start of the file
+            DLE, 0x80, 43, // an extended length, 127 + 5 + 3
+            DLE, 127, 5, 0,
+        ];
+
+        let mut previous = 0;
+        for symbol in symbols {
+            match (previous, symbol) {
+                // the followers of 'a' are written as an index into its set
+                (b'a', b'b') => w.bits(0b00, 2),
+                (b'a', b'c') => w.bits(0b10, 2),
+                // or as a flagged literal
+                (b'a', _) => {
+                    w.bits(1, 1);
+                    w.bits(u32::from(symbol), 8);
+                }
+                _ => w.bits(u32::from(symbol), 8),
+            }
+
+            previous = symbol;
+        }
+
+        let mut expected = b"abcabcabcab".to_vec();
+        expected.push(DLE);
+        expected.extend([0; 3 + 135]);
+
+        let mut decoder = ReduceDecoder::new(w.bytes.as_slice(), 1, expected.len() as u64);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out).unwrap();
+
+        assert_eq!(out, expected);
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    common::*,
+    decompress::{self, CheckedReader, DecoderOptions},
+    parse::{
+        find_end_central_directory, Parser, StoredCentralDirectory, END_CENTRAL_DIRECTORY_LEN,
+        LOCAL_FILE_HEADER_LEN, ZIP64_END_CENTRAL_DIRECTORY_LEN,
+        ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_LEN,
+    },
+    CentralDirectory, CentralDirectoryFileHeader, DecoderRegistry, Metadata, ZipParseError,
+};
+
+/// A ZIP archive read on demand from any [`Read`] + [`Seek`], such as a
+/// [`File`] or [`std::io::Cursor`]
+///
+/// Unlike [`crate::ZipArchive`], the archive is neither memory mapped nor
+/// loaded into memory. Only the central directory is read up front, and the
+/// contents of each file are read as they are decompressed.
+///
+/// ```no_run
+/// # use std::io::Read;
+/// # use zip_rs::ZipReader;
+/// let mut archive = ZipReader::from_path("./foo.zip").unwrap();
+///
+/// for i in 0..archive.len() {
+///     let mut file = archive.by_index(i).unwrap();
+///
+///     let mut contents = Vec::new();
+///     file.read_to_end(&mut contents).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ZipReader<R: Read + Seek> {
+    reader: R,
+    decoder_options: DecoderOptions,
+    /// The bytes of the central directory, which the file headers borrow
+    buffer: Vec<u8>,
+    central_directory: StoredCentralDirectory,
+}
+
+impl ZipReader<File> {
+    /// Open a [`ZipReader`] from a file path
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ZipParseError> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> ZipReader<R> {
+    /// Read the central directory of the archive in `reader`
+    pub fn new(mut reader: R) -> Result<Self, ZipParseError> {
+        let len = reader.seek(SeekFrom::End(0))?;
+
+        // the end of central directory record is followed only by its comment,
+        // and may be preceded by the zip64 locator
+        let max_tail_len = (END_CENTRAL_DIRECTORY_LEN
+            + usize::from(u16::MAX)
+            + ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_LEN) as u64;
+        let tail_start = len.saturating_sub(max_tail_len);
+
+        let tail = read_at(&mut reader, tail_start, (len - tail_start) as usize)?;
+
+        let offset =
+            find_end_central_directory(&tail).ok_or(ZipParseError::MissingCentralDirectory)?;
+
+        let mut tail_parser = Parser::new(&tail);
+        let mut end = tail_parser.read_end_central_directory(offset)?;
+
+        if let Some(zip64_end_offset) = tail_parser.read_zip64_locator(offset)? {
+            let record = read_at(
+                &mut reader,
+                zip64_end_offset,
+                ZIP64_END_CENTRAL_DIRECTORY_LEN,
+            )?;
+
+            Parser::new(&record).read_zip64_end_central_directory(0, &mut end)?;
+        }
+
+        let central_dir_size = usize::try_from(end.central_dir_size)
+            .map_err(|_| ZipParseError::FileTooLarge(end.central_dir_size))?;
+
+        // the central directory can't extend past the end of the archive
+        if end.central_dir_offset.saturating_add(end.central_dir_size) > len {
+            return Err(ZipParseError::UnexpectedEof);
+        }
+
+        let buffer = read_at(&mut reader, end.central_dir_offset, central_dir_size)?;
+
+        let files = Parser::new(&buffer).read_central_directory_file_headers(0)?;
+        let central_directory =
+            StoredCentralDirectory::new(&buffer, CentralDirectory { files, end });
+
+        Ok(ZipReader {
+            reader,
+            decoder_options: DecoderOptions::default(),
+            buffer,
+            central_directory,
+        })
+    }
+
+    /// The central directory, which lists every file in this archive.
+    ///
+    /// This collects the headers of every file, so prefer
+    /// [`ZipReader::header`] to look at only a few of them.
+    pub fn central_directory(&self) -> CentralDirectory<'_> {
+        self.central_directory.load(&self.buffer)
+    }
+
+    /// The central directory header of the file at `index`
+    pub fn header(&self, index: usize) -> Option<CentralDirectoryFileHeader<'_>> {
+        self.central_directory.header(&self.buffer, index)
+    }
+
+    /// The number of files in this archive
+    pub fn len(&self) -> usize {
+        self.central_directory.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The most memory, in bytes, a PPMd model may use. See
+    /// [`crate::CompressedZipFile::ppmd_memory_limit`].
+    pub fn set_ppmd_memory_limit(&mut self, limit: usize) {
+        self.decoder_options.ppmd_memory_limit = limit;
+    }
+
+    /// Decoders for compression methods this crate doesn't support itself.
+    /// See [`DecoderRegistry`].
+    pub fn set_decoders(&mut self, decoders: DecoderRegistry) {
+        self.decoder_options.registry = decoders;
+    }
+
+    /// The password used to decrypt encrypted files. See
+    /// [`crate::CompressedZipFile::password`].
+    pub fn set_password(&mut self, password: &[u8]) {
+        self.decoder_options.password = Some(password.to_vec());
+    }
+
+    /// Read the file at `index` in the central directory
+    pub fn by_index(&mut self, index: usize) -> Result<ZipFileReader<'_>, ZipParseError> {
+        let header = self
+            .central_directory
+            .header(&self.buffer, index)
+            .ok_or(ZipParseError::Generic("file index out of bounds"))?;
+
+        let local_header = read_at(
+            &mut self.reader,
+            header.local_header_offset,
+            LOCAL_FILE_HEADER_LEN,
+        )?;
+
+        let found = [
+            local_header[0],
+            local_header[1],
+            local_header[2],
+            local_header[3],
+        ];
+
+        if found != LOCAL_FILE_SIGNATURE {
+            return Err(ZipParseError::MalformedSignature {
+                found,
+                expected: LOCAL_FILE_SIGNATURE,
+            });
+        }
+
+        // the name and extra field may differ from those in the central
+        // directory, so we only need their lengths to find the contents
+        let file_name_len = u16::from_le_bytes([local_header[26], local_header[27]]);
+        let extra_field_len = u16::from_le_bytes([local_header[28], local_header[29]]);
+
+        self.reader.seek(SeekFrom::Current(
+            i64::from(file_name_len) + i64::from(extra_field_len),
+        ))?;
+
+        let metadata = header.metadata;
+
+        let contents = BufReader::new((&mut self.reader).take(metadata.compressed_size));
+        let decoder = decompress::decoder(&metadata, contents, &self.decoder_options)?;
+        let decoder = Box::new(CheckedReader::new(decoder, &metadata));
+
+        Ok(ZipFileReader { metadata, decoder })
+    }
+}
+
+/// A single file read from a [`ZipReader`]. The decompressed contents are read
+/// through this type's [`Read`] implementation.
+///
+/// Reading fails if the contents are larger than [`Metadata::uncompressed_size`],
+/// or once the end is reached if they are smaller or their CRC-32 is wrong.
+pub struct ZipFileReader<'a> {
+    pub metadata: Metadata<'a>,
+    decoder: Box<dyn Read + 'a>,
+}
+
+impl std::fmt::Debug for ZipFileReader<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZipFileReader")
+            .field("metadata", &self.metadata)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> ZipFileReader<'a> {
+    /// The raw bytes of this file's path inside the ZIP archive.
+    ///
+    /// Note that this path may reference file paths outside the archive through
+    /// the use of absolute paths or the parent directory (`..`). The full file path
+    /// should not be used when interacting with the host file system if the ZIP
+    /// file is untrusted.
+    pub fn file_path_bytes(&self) -> &'a [u8] {
+        self.metadata.name
+    }
+
+    /// This file's path inside the ZIP archive, decoded as text. See
+    /// [`Metadata::file_name`].
+    pub fn file_name(&self) -> Cow<'a, str> {
+        self.metadata.file_name()
+    }
+}
+
+impl Read for ZipFileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+fn read_at<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>, ZipParseError> {
+    let mut buffer = vec![0; len];
+
+    reader.seek(SeekFrom::Start(offset))?;
+    reader
+        .read_exact(&mut buffer)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => ZipParseError::UnexpectedEof,
+            _ => err.into(),
+        })?;
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor, Read, Write};
+
+    use crate::{
+        CompressionMethod, FileOptions, ZipParseError, ZipReader, ZipWriter,
+        CENTRAL_DIRECTORY_FILE_SIGNATURE,
+    };
+
+    #[test]
+    fn read_from_cursor() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        writer
+            .start_file(
+                "stored",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+        writer.write_all(b"stored contents").unwrap();
+
+        writer
+            .start_file("deflated", FileOptions::default())
+            .unwrap();
+        writer.write_all(&b"deflated ".repeat(100)).unwrap();
+
+        writer.set_comment("x".repeat(1000));
+
+        let cursor = writer.finish().unwrap();
+        let mut archive = ZipReader::new(cursor).unwrap();
+
+        assert_eq!(archive.len(), 2);
+
+        let mut contents = Vec::new();
+        archive
+            .by_index(1)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"deflated ".repeat(100));
+
+        let mut file = archive.by_index(0).unwrap();
+        assert_eq!(file.file_path_bytes(), b"stored");
+
+        contents.clear();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"stored contents");
+
+        drop(file);
+
+        assert!(archive.by_index(2).is_err());
+    }
+
+    #[test]
+    fn corrupt_contents() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.start_file("a", FileOptions::default()).unwrap();
+        writer.write_all(&b"a".repeat(100)).unwrap();
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let central = memchr::memmem::find(&buffer, &CENTRAL_DIRECTORY_FILE_SIGNATURE).unwrap();
+
+        let mut corrupt_crc = buffer.clone();
+        corrupt_crc[central + 16] ^= 1;
+
+        let mut archive = ZipReader::new(Cursor::new(corrupt_crc)).unwrap();
+        let err = archive
+            .by_index(0)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+
+        assert!(matches!(
+            err.into_inner()
+                .unwrap()
+                .downcast::<ZipParseError>()
+                .as_deref(),
+            Ok(ZipParseError::CrcMismatch { .. })
+        ));
+
+        // the decoder is stopped once it passes the declared size
+        let mut understated_size = buffer;
+        understated_size[central + 24..central + 28].copy_from_slice(&10u32.to_le_bytes());
+
+        let mut archive = ZipReader::new(Cursor::new(understated_size)).unwrap();
+        let mut contents = Vec::new();
+        let err = archive
+            .by_index(0)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(contents.len() <= 10);
+    }
+}
@@ -0,0 +1,277 @@
+//! A decoder for Shrink, the LZW variant used by early versions of PKZIP.
+//!
+//! Codes start out 9 bits long and grow up to 13 bits when the encoder says so.
+//! Rather than resetting the whole dictionary when it fills up, the encoder may
+//! ask for a partial clear, which frees every code that is not the prefix of
+//! another code.
+
+use std::io::{self, BufRead, Read};
+
+use crate::bits::{invalid_data, BitReader};
+
+const MIN_CODE_SIZE: u32 = 9;
+const MAX_CODE_SIZE: u32 = 13;
+const MAX_CODE: usize = 1 << MAX_CODE_SIZE;
+
+/// Followed by a 1 to increase the code size, or a 2 for a partial clear
+const CONTROL_CODE: u16 = 256;
+const FIRST_FREE_CODE: u16 = 257;
+
+const INCREASE_CODE_SIZE: u32 = 1;
+const PARTIAL_CLEAR: u32 = 2;
+
+/// Marks an entry in the dictionary which is not in use
+const FREE: u16 = u16::MAX;
+
+/// Streaming Shrink decoder, which stops once `uncompressed_size` bytes have
+/// been produced
+#[derive(Debug)]
+pub(crate) struct ShrinkDecoder<R> {
+    bits: BitReader<R>,
+    code_size: u32,
+    /// The code each entry extends, or [`FREE`]. Codes below 256 are literals.
+    prefixes: Vec<u16>,
+    /// The last byte of each entry's string
+    suffixes: Vec<u8>,
+    /// Lowest unused code, or [`MAX_CODE`] once the dictionary is full
+    next_free: u16,
+    /// The previously decoded code, or `None` at the start of the stream
+    previous: Option<u16>,
+    /// The string of the last code, reversed, which has not been read yet
+    pending: Vec<u8>,
+    remaining: u64,
+}
+
+impl<R: BufRead> ShrinkDecoder<R> {
+    pub(crate) fn new(reader: R, uncompressed_size: u64) -> Self {
+        let mut prefixes = vec![FREE; MAX_CODE];
+        let mut suffixes = vec![0; MAX_CODE];
+
+        for byte in 0..=u8::MAX {
+            prefixes[usize::from(byte)] = u16::from(byte);
+            suffixes[usize::from(byte)] = byte;
+        }
+
+        Self {
+            bits: BitReader::new(reader),
+            code_size: MIN_CODE_SIZE,
+            prefixes,
+            suffixes,
+            next_free: FIRST_FREE_CODE,
+            previous: None,
+            pending: Vec::new(),
+            remaining: uncompressed_size,
+        }
+    }
+
+    /// Read the next code which is not a control code
+    fn read_code(&mut self) -> io::Result<u16> {
+        loop {
+            let code = self.bits.bits(self.code_size)? as u16;
+
+            if code != CONTROL_CODE {
+                return Ok(code);
+            }
+
+            match self.bits.bits(self.code_size)? {
+                INCREASE_CODE_SIZE if self.code_size < MAX_CODE_SIZE => self.code_size += 1,
+                PARTIAL_CLEAR => self.partial_clear(),
+                _ => return Err(invalid_data("invalid shrink control code")),
+            }
+        }
+    }
+
+    /// Free every code which no other code extends
+    fn partial_clear(&mut self) {
+        let first = usize::from(FIRST_FREE_CODE);
+        let mut is_prefix = vec![false; MAX_CODE];
+
+        for &prefix in &self.prefixes[first..] {
+            if prefix != FREE {
+                is_prefix[usize::from(prefix)] = true;
+            }
+        }
+
+        for (prefix, &is_prefix) in self.prefixes[first..].iter_mut().zip(&is_prefix[first..]) {
+            if !is_prefix {
+                *prefix = FREE;
+            }
+        }
+
+        self.next_free = FIRST_FREE_CODE;
+        self.find_free();
+    }
+
+    /// Move `next_free` to the lowest unused code, if there is one
+    fn find_free(&mut self) {
+        while usize::from(self.next_free) < MAX_CODE
+            && self.prefixes[usize::from(self.next_free)] != FREE
+        {
+            self.next_free += 1;
+        }
+    }
+
+    /// Push the string of `code` onto `pending`, last byte first
+    fn push_string(&mut self, mut code: u16) -> io::Result<()> {
+        let start = self.pending.len();
+
+        while code > CONTROL_CODE {
+            if code == FREE {
+                return Err(invalid_data("invalid shrink code"));
+            }
+
+            // a well formed dictionary can't have chains longer than itself
+            if self.pending.len() - start >= MAX_CODE {
+                return Err(invalid_data("self-referential shrink code"));
+            }
+
+            self.pending.push(self.suffixes[usize::from(code)]);
+            code = self.prefixes[usize::from(code)];
+        }
+
+        if code == CONTROL_CODE {
+            return Err(invalid_data("invalid shrink code"));
+        }
+
+        self.pending.push(code as u8);
+
+        Ok(())
+    }
+
+    fn decode_next(&mut self) -> io::Result<()> {
+        let code = self.read_code()?;
+
+        let Some(previous) = self.previous else {
+            if code > u16::from(u8::MAX) {
+                return Err(invalid_data("first shrink code must be a literal"));
+            }
+
+            self.pending.push(code as u8);
+            self.previous = Some(code);
+            return Ok(());
+        };
+
+        if self.prefixes[usize::from(code)] == FREE {
+            // the only unused code the encoder may refer to is the one it is
+            // about to add, which is the previous string followed by its own
+            // first byte
+            if code != self.next_free || self.prefixes[usize::from(previous)] == FREE {
+                return Err(invalid_data("invalid shrink code"));
+            }
+
+            self.push_string(previous)?;
+            let first = self.pending[self.pending.len() - 1];
+            self.pending.insert(0, first);
+        } else {
+            self.push_string(code)?;
+        }
+
+        let first = self.pending[self.pending.len() - 1];
+
+        // once the dictionary is full, new strings are no longer added
+        if usize::from(self.next_free) < MAX_CODE {
+            self.prefixes[usize::from(self.next_free)] = previous;
+            self.suffixes[usize::from(self.next_free)] = first;
+            self.find_free();
+        }
+
+        self.previous = Some(code);
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ShrinkDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        while n < buf.len() && self.remaining > 0 {
+            match self.pending.pop() {
+                Some(byte) => {
+                    buf[n] = byte;
+                    n += 1;
+                    self.remaining -= 1;
+                }
+                None => self.decode_next()?,
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::bits::BitWriter;
+
+    use super::ShrinkDecoder;
+
+    #[test]
+    fn unshrink() {
+        let compressed = [
+            0x61, 0xc4, 0x04, 0x1c, 0x23, 0xb0, 0x60, 0x98, 0x83, 0x08, 0xc3, 0x00,
+        ];
+        let expected = b"ababcbababaaaaaaa";
+
+        let mut decoder = ShrinkDecoder::new(&compressed[..], expected.len() as u64);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out).unwrap();
+
+        assert_eq!(out, expected);
+    }
+
+    /// Pack `(code, size)` pairs least significant bit first
+    fn pack(codes: &[(u32, usize)]) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+
+        for &(code, size) in codes {
+            writer.bits(code, size);
+        }
+
+        writer.bytes
+    }
+
+    #[test]
+    fn control_codes() {
+        let compressed = pack(&[
+            (u32::from(b'a'), 9),
+            (u32::from(b'b'), 9),
+            (u32::from(b'c'), 9),
+            // partial clear, freeing "ab" (257) and "bc" (258)
+            (256, 9),
+            (2, 9),
+            (u32::from(b'a'), 9),
+            // "ca", added by the previous code
+            (257, 9),
+            // increase the code size
+            (256, 9),
+            (1, 9),
+            // "ac", adding "caa" as 259
+            (258, 10),
+            // not yet added, so "ac" followed by its own first byte
+            (260, 10),
+        ]);
+        let expected = b"abcacaacaca";
+
+        let mut decoder = ShrinkDecoder::new(&compressed[..], expected.len() as u64);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out).unwrap();
+
+        assert_eq!(out, expected);
+
+        // 258 is unused after the partial clear, and isn't the next free code
+        let compressed = pack(&[
+            (u32::from(b'a'), 9),
+            (u32::from(b'b'), 9),
+            (u32::from(b'c'), 9),
+            (256, 9),
+            (2, 9),
+            (258, 9),
+        ]);
+
+        let mut decoder = ShrinkDecoder::new(&compressed[..], 10);
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    }
+}
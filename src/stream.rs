@@ -0,0 +1,820 @@
+use std::{
+    borrow::Cow,
+    io::{self, BufRead, BufReader, Read},
+};
+
+use crc32fast::Hasher;
+
+use crate::{
+    common::*,
+    decompress::{self, CheckedReader, DecoderOptions},
+    parse::Parser,
+    zipcrypto::{self, Keys},
+    DecoderRegistry, Metadata, ZipParseError,
+};
+
+/// Largest possible data descriptor, including the optional signature and
+/// 64-bit sizes
+const MAX_DATA_DESCRIPTOR_LEN: usize = 24;
+
+/// Local file header, excluding the signature, name and extra field
+const LOCAL_FILE_HEADER_LEN: usize = 26;
+
+const BUFFER_LEN: usize = 8 * 1024;
+
+/// Read a ZIP archive front to back from any [`Read`], without seeking or
+/// loading it into memory
+///
+/// Files are found by walking their local file headers in order, so the
+/// central directory is never consulted. This means that the comments and
+/// external attributes it contains are unavailable, and that files which were
+/// deleted or replaced by appending to an archive may be yielded.
+///
+/// ```no_run
+/// # use std::io::Read;
+/// # use zip_rs::ZipStreamReader;
+/// let mut reader = ZipStreamReader::new(std::io::stdin());
+///
+/// while let Some(mut file) = reader.next_entry().unwrap() {
+///     let mut contents = Vec::new();
+///     file.read_to_end(&mut contents).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ZipStreamReader<R: Read> {
+    source: Source<R>,
+    /// The local file header of the current file, excluding its signature
+    header: Vec<u8>,
+    data: DataState,
+    entry: EntryState,
+    decoder_options: DecoderOptions,
+    done: bool,
+}
+
+impl<R: Read> ZipStreamReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            source: Source::new(inner),
+            header: Vec::new(),
+            data: DataState::default(),
+            entry: EntryState::default(),
+            decoder_options: DecoderOptions::default(),
+            done: false,
+        }
+    }
+
+    /// The most memory, in bytes, a PPMd model may use. See
+    /// [`crate::CompressedZipFile::ppmd_memory_limit`].
+    pub fn set_ppmd_memory_limit(&mut self, limit: usize) {
+        self.decoder_options.ppmd_memory_limit = limit;
+    }
+
+    /// Decoders for compression methods this crate doesn't support itself.
+    /// See [`DecoderRegistry`].
+    pub fn set_decoders(&mut self, decoders: DecoderRegistry) {
+        self.decoder_options.registry = decoders;
+    }
+
+    /// The password used to decrypt encrypted files. See
+    /// [`crate::CompressedZipFile::password`].
+    pub fn set_password(&mut self, password: &[u8]) {
+        self.decoder_options.password = Some(password.to_vec());
+    }
+
+    /// Advance to the next file in the archive, skipping any unread contents
+    /// of the previous file.
+    ///
+    /// Returns `None` once the central directory is reached.
+    pub fn next_entry(&mut self) -> Result<Option<ZipStreamEntry<'_>>, ZipParseError> {
+        if let Some(err) = self.entry.error.take() {
+            self.done = true;
+            return Err(err);
+        }
+
+        self.finish_entry()?;
+
+        if self.done {
+            return Ok(None);
+        }
+
+        let mut signature = [0; 4];
+
+        if self.source.fill_at_least(4)?.is_empty() {
+            self.done = true;
+            return Ok(None);
+        }
+
+        self.source.read_exact(&mut signature)?;
+
+        match signature {
+            LOCAL_FILE_SIGNATURE => {}
+            CENTRAL_DIRECTORY_FILE_SIGNATURE
+            | ZIP64_END_CENTRAL_DIRECTORY_SIGNATURE
+            | END_CENTRAL_DIRECTORY_SIGNATURE => {
+                self.done = true;
+                return Ok(None);
+            }
+            found => {
+                self.done = true;
+                return Err(ZipParseError::MalformedSignature {
+                    found,
+                    expected: LOCAL_FILE_SIGNATURE,
+                });
+            }
+        }
+
+        self.header.resize(LOCAL_FILE_HEADER_LEN, 0);
+        self.source.read_exact(&mut self.header)?;
+
+        let file_name_len = u16::from_le_bytes([self.header[22], self.header[23]]);
+        let extra_field_len = u16::from_le_bytes([self.header[24], self.header[25]]);
+
+        self.header.resize(
+            LOCAL_FILE_HEADER_LEN + usize::from(file_name_len) + usize::from(extra_field_len),
+            0,
+        );
+        self.source
+            .read_exact(&mut self.header[LOCAL_FILE_HEADER_LEN..])?;
+
+        let metadata = Parser::new(&self.header[..]).read_metadata()?;
+
+        // writers which use a data descriptor may still record the sizes in
+        // the local header, in which case we can trust them
+        let size_known = !metadata.flags.has_data_descriptor() || metadata.compressed_size != 0;
+
+        self.data = DataState {
+            remaining: size_known.then_some(metadata.compressed_size),
+            consumed: 0,
+        };
+
+        self.entry = EntryState {
+            active: true,
+            has_data_descriptor: metadata.flags.has_data_descriptor(),
+            zip64: crate::extra::find_extra_field(metadata.extra_field, ZIP64_EXTRA_FIELD_ID)
+                .is_some(),
+            must_drain: !size_known,
+            produced: 0,
+            hasher: Hasher::new(),
+            check_crc: metadata.has_crc(),
+            reached_end: false,
+            error: None,
+        };
+
+        // the authentication code follows the contents, so their end must be
+        // known to check it
+        if !size_known && metadata.compression_method == CompressionMethod::AES {
+            self.done = true;
+            return Err(ZipParseError::Generic("aes encrypted file of unknown size"));
+        }
+
+        // these decoders stop after the uncompressed size rather than at an end
+        // of stream marker, so they can't find the end of the contents either.
+        // Stored contents are instead searched for their data descriptor.
+        let stored = metadata.compression_method.name() == CompressionMethodName::None;
+
+        if !size_known && !stored && !ends_by_itself(&metadata) {
+            self.done = true;
+            return Err(ZipParseError::Generic(
+                "file of unknown size can't be decompressed without it",
+            ));
+        }
+
+        let mut decoder: Box<dyn Read + '_> = if !size_known && stored {
+            // with encryption, the descriptor describes the plaintext, so
+            // the scanner decrypts a copy of the contents to recognize it
+            let keys = match (
+                &self.decoder_options.password,
+                metadata.flags.is_encrypted(),
+            ) {
+                (Some(password), true) => Some(Keys::new(password)),
+                _ => None,
+            };
+
+            let scanner = DataDescriptorScanner {
+                source: &mut self.source,
+                data: &mut self.data,
+                hasher: Hasher::new(),
+                keys,
+                done: false,
+            };
+
+            match decompress::decoder(&metadata, BufReader::new(scanner), &self.decoder_options) {
+                Ok(decoder) => decoder,
+                Err(err) => {
+                    self.done = true;
+                    return Err(err);
+                }
+            }
+        } else {
+            let data = EntryData {
+                source: &mut self.source,
+                data: &mut self.data,
+            };
+
+            match decompress::decoder(&metadata, data, &self.decoder_options) {
+                Ok(decoder) => decoder,
+                Err(err) => {
+                    self.done = true;
+                    return Err(err);
+                }
+            }
+        };
+
+        // a data descriptor is checked once it has been read, after the contents
+        if !metadata.flags.has_data_descriptor() {
+            decoder = Box::new(CheckedReader::new(decoder, &metadata));
+        }
+
+        Ok(Some(ZipStreamEntry {
+            metadata,
+            decoder,
+            state: &mut self.entry,
+        }))
+    }
+
+    /// Skip any unread contents of the current file, along with its data
+    /// descriptor
+    fn finish_entry(&mut self) -> Result<(), ZipParseError> {
+        if !self.entry.active {
+            return Ok(());
+        }
+
+        self.entry.active = false;
+
+        if let Some(remaining) = self.data.remaining.take() {
+            let skipped = io::copy(&mut (&mut self.source).take(remaining), &mut io::sink())?;
+
+            if skipped != remaining {
+                return Err(ZipParseError::UnexpectedEof);
+            }
+
+            self.data.consumed += skipped;
+        }
+
+        if self.entry.has_data_descriptor {
+            self.read_data_descriptor()?;
+        }
+
+        Ok(())
+    }
+
+    fn read_data_descriptor(&mut self) -> Result<(), ZipParseError> {
+        let zip64 = self.entry.zip64
+            || self.data.consumed >= u64::from(u32::MAX)
+            || self.entry.produced >= u64::from(u32::MAX);
+
+        let len = if zip64 { 20 } else { 12 };
+
+        let mut buffer = [0; MAX_DATA_DESCRIPTOR_LEN];
+
+        self.source.read_exact(&mut buffer[..4])?;
+
+        // the signature is optional
+        if buffer[..4] == DATA_DESCRIPTOR_SIGNATURE {
+            self.source.read_exact(&mut buffer[..len])?;
+        } else {
+            self.source.read_exact(&mut buffer[4..len])?;
+        }
+
+        // contents which were skipped rather than read can't be checked
+        if !self.entry.reached_end {
+            return Ok(());
+        }
+
+        let field = |start: usize, len: usize| {
+            buffer[start..start + len]
+                .iter()
+                .rev()
+                .fold(0, |acc, &b| (acc << 8) | u64::from(b))
+        };
+
+        let width = if zip64 { 8 } else { 4 };
+        let compressed_size = field(4, width);
+        let uncompressed_size = field(4 + width, width);
+
+        if compressed_size != self.data.consumed || uncompressed_size != self.entry.produced {
+            return Err(ZipParseError::Generic(
+                "data descriptor sizes don't match the contents",
+            ));
+        }
+
+        let expected = field(0, 4) as u32;
+        let found = self.entry.hasher.clone().finalize();
+
+        if self.entry.check_crc && found != expected {
+            return Err(ZipParseError::CrcMismatch { expected, found });
+        }
+
+        Ok(())
+    }
+}
+
+/// A single file read from a [`ZipStreamReader`]. The decompressed contents
+/// are read through this type's [`Read`] implementation.
+///
+/// Note that when a data descriptor is used, the crc and sizes in `metadata`
+/// are typically 0. The contents are then checked against the data descriptor
+/// instead, which follows them, so a mismatch is returned by the next call to
+/// [`ZipStreamReader::next_entry`] rather than by [`Read::read`].
+pub struct ZipStreamEntry<'r> {
+    pub metadata: Metadata<'r>,
+    decoder: Box<dyn Read + 'r>,
+    state: &'r mut EntryState,
+}
+
+impl std::fmt::Debug for ZipStreamEntry<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZipStreamEntry")
+            .field("metadata", &self.metadata)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'r> ZipStreamEntry<'r> {
+    /// The raw bytes of this file's path inside the ZIP archive.
+    ///
+    /// Note that this path may reference file paths outside the archive through
+    /// the use of absolute paths or the parent directory (`..`). The full file path
+    /// should not be used when interacting with the host file system if the ZIP
+    /// file is untrusted.
+    pub fn file_path_bytes(&self) -> &'r [u8] {
+        self.metadata.name
+    }
+
+    /// This file's path inside the ZIP archive, decoded as text. See
+    /// [`Metadata::file_name`].
+    pub fn file_name(&self) -> Cow<'r, str> {
+        self.metadata.file_name()
+    }
+}
+
+impl Read for ZipStreamEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.decoder.read(buf)?;
+
+        self.state.produced += n as u64;
+        self.state.hasher.update(&buf[..n]);
+
+        if n == 0 && !buf.is_empty() {
+            self.state.reached_end = true;
+        }
+
+        Ok(n)
+    }
+}
+
+impl Drop for ZipStreamEntry<'_> {
+    fn drop(&mut self) {
+        // when the compressed size isn't known up front, the only way to find
+        // the end of the contents is to decompress them
+        if self.state.must_drain {
+            if let Err(err) = io::copy(self, &mut io::sink()) {
+                self.state.error = Some(err.into());
+            }
+        }
+    }
+}
+
+/// Whether the decoder for `metadata` finds the end of the contents by itself,
+/// rather than stopping once it has produced the uncompressed size
+fn ends_by_itself(metadata: &Metadata) -> bool {
+    match metadata.compression_method.name() {
+        CompressionMethodName::None
+        | CompressionMethodName::Shrink
+        | CompressionMethodName::Factor1
+        | CompressionMethodName::Factor2
+        | CompressionMethodName::Factor3
+        | CompressionMethodName::Factor4
+        | CompressionMethodName::Implode
+        | CompressionMethodName::PPMd => false,
+        // without an end of stream marker, LZMA relies on the size
+        CompressionMethodName::Lzma => metadata.flags.0 & ZipFlags::COMPRESSION_OPTION1 != 0,
+        _ => true,
+    }
+}
+
+/// Progress through the compressed contents of the current file
+#[derive(Debug, Default)]
+struct DataState {
+    /// `None` if the compressed size is not known until the data descriptor
+    remaining: Option<u64>,
+    consumed: u64,
+}
+
+#[derive(Debug, Default)]
+struct EntryState {
+    active: bool,
+    has_data_descriptor: bool,
+    zip64: bool,
+    must_drain: bool,
+    produced: u64,
+    /// The crc of the decompressed contents read so far
+    hasher: Hasher,
+    check_crc: bool,
+    /// Whether the contents have been decompressed to the end, and so can be
+    /// checked against the data descriptor
+    reached_end: bool,
+    /// An error encountered while skipping a dropped entry
+    error: Option<ZipParseError>,
+}
+
+/// The compressed contents of the current file, limited to its compressed size
+/// if it is known
+struct EntryData<'r, R: Read> {
+    source: &'r mut Source<R>,
+    data: &'r mut DataState,
+}
+
+impl<'r, R: Read> Read for EntryData<'r, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+
+        Ok(n)
+    }
+}
+
+impl<'r, R: Read> BufRead for EntryData<'r, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let remaining = self.data.remaining;
+        let available = self.source.fill_buf()?;
+
+        Ok(match remaining {
+            Some(remaining) => &available[..available.len().min(remaining as usize)],
+            None => available,
+        })
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.source.consume(amt);
+        self.data.consumed += amt as u64;
+
+        if let Some(remaining) = self.data.remaining.as_mut() {
+            *remaining -= amt as u64;
+        }
+    }
+}
+
+/// Stored contents of unknown length, which can only be found by searching
+/// for a data descriptor whose crc and sizes match the data preceding it
+struct DataDescriptorScanner<'r, R: Read> {
+    source: &'r mut Source<R>,
+    data: &'r mut DataState,
+    /// The crc of the plaintext contents read so far
+    hasher: Hasher,
+    /// The state of the cipher after the bytes read so far, if the contents
+    /// are encrypted. The bytes read are still returned encrypted.
+    keys: Option<Keys>,
+    done: bool,
+}
+
+/// Hash the plaintext of `bytes`, which follow the `consumed` bytes already
+/// hashed, skipping the encryption header of encrypted contents
+fn hash_plaintext(hasher: &mut Hasher, keys: Option<&mut Keys>, consumed: u64, bytes: &[u8]) {
+    let Some(keys) = keys else {
+        hasher.update(bytes);
+        return;
+    };
+
+    let mut plaintext = bytes.to_vec();
+    keys.decrypt(&mut plaintext);
+
+    let header = (zipcrypto::HEADER_LEN as u64).saturating_sub(consumed) as usize;
+    hasher.update(&plaintext[header.min(plaintext.len())..]);
+}
+
+/// Whether `window[offset..]` begins with a data descriptor for the
+/// `consumed` bytes hashed by `hasher` followed by `window[..offset]`
+fn is_data_descriptor(
+    hasher: &Hasher,
+    keys: Option<&Keys>,
+    consumed: u64,
+    window: &[u8],
+    offset: usize,
+) -> bool {
+    if !window[offset..].starts_with(&DATA_DESCRIPTOR_SIGNATURE) {
+        return false;
+    }
+
+    let size = consumed + offset as u64;
+    let plaintext_size = match keys {
+        Some(_) => size.checked_sub(zipcrypto::HEADER_LEN as u64),
+        None => Some(size),
+    };
+
+    let field = |start: usize, len: usize| {
+        window
+            .get(offset + start..offset + start + len)
+            .map(|b| b.iter().rev().fold(0, |acc, &b| (acc << 8) | u64::from(b)))
+    };
+
+    let sizes_match = |compressed: Option<u64>, uncompressed: Option<u64>| {
+        compressed == Some(size) && uncompressed.is_some() && uncompressed == plaintext_size
+    };
+
+    if !sizes_match(field(8, 4), field(12, 4)) && !sizes_match(field(8, 8), field(16, 8)) {
+        return false;
+    }
+
+    let mut hasher = hasher.clone();
+    let mut keys = keys.cloned();
+    hash_plaintext(&mut hasher, keys.as_mut(), consumed, &window[..offset]);
+
+    field(4, 4) == Some(u64::from(hasher.finalize()))
+}
+
+impl<'r, R: Read> Read for DataDescriptorScanner<'r, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let want = buf.len().min(BUFFER_LEN) + MAX_DATA_DESCRIPTOR_LEN;
+        let window = self.source.fill_at_least(want)?;
+        let at_eof = window.len() < want;
+
+        let descriptor = (0..window.len()).find(|&i| {
+            is_data_descriptor(
+                &self.hasher,
+                self.keys.as_ref(),
+                self.data.consumed,
+                window,
+                i,
+            )
+        });
+
+        let available = match descriptor {
+            Some(offset) => offset,
+            // keep enough bytes around to recognize a descriptor which has only
+            // been partially read
+            None if !at_eof => window.len() - (MAX_DATA_DESCRIPTOR_LEN - 1),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+
+        if available == 0 {
+            self.done = true;
+            return Ok(0);
+        }
+
+        let n = available.min(buf.len());
+
+        buf[..n].copy_from_slice(&window[..n]);
+        hash_plaintext(
+            &mut self.hasher,
+            self.keys.as_mut(),
+            self.data.consumed,
+            &window[..n],
+        );
+        self.source.consume(n);
+        self.data.consumed += n as u64;
+
+        Ok(n)
+    }
+}
+
+/// A buffered reader which can guarantee a minimum amount of lookahead
+#[derive(Debug)]
+struct Source<R: Read> {
+    inner: R,
+    buffer: Vec<u8>,
+    pos: usize,
+    end: usize,
+}
+
+impl<R: Read> Source<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: vec![0; BUFFER_LEN],
+            pos: 0,
+            end: 0,
+        }
+    }
+
+    /// Buffer at least `n` bytes, unless the end of the stream is reached first
+    fn fill_at_least(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.end - self.pos < n {
+            self.buffer.copy_within(self.pos..self.end, 0);
+            self.end -= self.pos;
+            self.pos = 0;
+
+            if self.buffer.len() < n {
+                self.buffer.resize(n, 0);
+            }
+
+            while self.end < n {
+                match self.inner.read(&mut self.buffer[self.end..]) {
+                    Ok(0) => break,
+                    Ok(read) => self.end += read,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(&self.buffer[self.pos..self.end])
+    }
+}
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for Source<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.end {
+            self.pos = 0;
+            self.end = self.inner.read(&mut self.buffer)?;
+        }
+
+        Ok(&self.buffer[self.pos..self.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.end);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read, Write};
+
+    use crate::{
+        zipcrypto, CompressionMethod, FileOptions, ZipFlags, ZipParseError, ZipStreamReader,
+        ZipStreamWriter, ZipWriter, DATA_DESCRIPTOR_SIGNATURE, LOCAL_FILE_SIGNATURE,
+    };
+
+    fn read_all(buffer: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut reader = ZipStreamReader::new(buffer);
+        let mut files = Vec::new();
+
+        while let Some(mut file) = reader.next_entry().unwrap() {
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).unwrap();
+            files.push((file.file_path_bytes().to_vec(), contents));
+        }
+
+        files
+    }
+
+    #[test]
+    fn data_descriptors() {
+        let mut writer = ZipStreamWriter::new(Vec::new());
+
+        writer
+            .start_file("deflated", FileOptions::default())
+            .unwrap();
+        writer.write_all(&b"deflated ".repeat(100)).unwrap();
+
+        writer
+            .start_file(
+                "stored",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+        // contains a data descriptor signature which doesn't match the contents
+        writer.write_all(b"PK\x07\x08 stored").unwrap();
+
+        writer
+            .add_directory("empty", FileOptions::default())
+            .unwrap();
+
+        let files = read_all(&writer.finish().unwrap());
+
+        assert_eq!(
+            files,
+            vec![
+                (b"deflated".to_vec(), b"deflated ".repeat(100)),
+                (b"stored".to_vec(), b"PK\x07\x08 stored".to_vec()),
+                (b"empty/".to_vec(), Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_unread_entries() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        for (name, method) in [
+            ("a", CompressionMethod::DEFLATE),
+            ("b", CompressionMethod::STORED),
+            ("c", CompressionMethod::DEFLATE),
+        ] {
+            writer
+                .start_file(name, FileOptions::default().compression_method(method))
+                .unwrap();
+            writer.write_all(name.repeat(50).as_bytes()).unwrap();
+        }
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let mut reader = ZipStreamReader::new(&buffer[..]);
+
+        let mut names = Vec::new();
+
+        while let Some(file) = reader.next_entry().unwrap() {
+            names.push(file.file_path_bytes().to_vec());
+        }
+
+        assert_eq!(names, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        assert_eq!(read_all(&buffer)[2].1, b"c".repeat(50));
+    }
+
+    #[test]
+    fn corrupt_contents() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.start_file("a", FileOptions::default()).unwrap();
+        writer.write_all(&b"a".repeat(100)).unwrap();
+
+        // without a data descriptor, the local header's crc is checked at the
+        // end of the contents
+        let mut buffer = writer.finish().unwrap().into_inner();
+        buffer[14] ^= 1;
+
+        let mut reader = ZipStreamReader::new(&buffer[..]);
+        let mut file = reader.next_entry().unwrap().unwrap();
+        let err = file.read_to_end(&mut Vec::new()).unwrap_err();
+
+        assert!(matches!(
+            err.into_inner()
+                .unwrap()
+                .downcast::<ZipParseError>()
+                .as_deref(),
+            Ok(ZipParseError::CrcMismatch { .. })
+        ));
+
+        let mut writer = ZipStreamWriter::new(Vec::new());
+        writer.start_file("a", FileOptions::default()).unwrap();
+        writer.write_all(&b"a".repeat(100)).unwrap();
+
+        let mut buffer = writer.finish().unwrap();
+        let descriptor = memchr::memmem::find(&buffer, &DATA_DESCRIPTOR_SIGNATURE).unwrap();
+        buffer[descriptor + 4] ^= 1;
+
+        let mut reader = ZipStreamReader::new(&buffer[..]);
+        let mut file = reader.next_entry().unwrap().unwrap();
+        file.read_to_end(&mut Vec::new()).unwrap();
+        drop(file);
+
+        assert!(matches!(
+            reader.next_entry(),
+            Err(ZipParseError::CrcMismatch { .. })
+        ));
+
+        // an imploded file can't be decompressed without its size
+        buffer[descriptor + 4] ^= 1;
+        buffer[8] = 6;
+
+        let mut reader = ZipStreamReader::new(&buffer[..]);
+        assert!(matches!(
+            reader.next_entry(),
+            Err(ZipParseError::Generic(..))
+        ));
+    }
+
+    #[test]
+    fn encrypted_data_descriptor() {
+        let contents = b"encrypted contents";
+
+        // a stored file of unknown size, whose check byte is the high byte of
+        // the modification time
+        let mut buffer = LOCAL_FILE_SIGNATURE.to_vec();
+        buffer.extend(20u16.to_le_bytes());
+        buffer.extend((ZipFlags::ENCRYPTED_FILE | ZipFlags::DATA_DESCRIPTOR).to_le_bytes());
+        buffer.extend(0u16.to_le_bytes());
+        buffer.extend(0x5a21_6b3cu32.to_le_bytes());
+        buffer.extend([0; 12]);
+        buffer.extend(1u16.to_le_bytes());
+        buffer.extend(0u16.to_le_bytes());
+        buffer.push(b'a');
+        buffer.extend(zipcrypto::test::encrypt(contents, b"secret", 0x6b));
+        buffer.extend(DATA_DESCRIPTOR_SIGNATURE);
+        buffer.extend(crc32fast::hash(contents).to_le_bytes());
+        buffer.extend((contents.len() as u32 + 12).to_le_bytes());
+        buffer.extend((contents.len() as u32).to_le_bytes());
+
+        let mut reader = ZipStreamReader::new(&buffer[..]);
+        reader.set_password(b"secret");
+
+        let mut file = reader.next_entry().unwrap().unwrap();
+        let mut decrypted = Vec::new();
+        file.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, contents);
+
+        drop(file);
+        assert!(reader.next_entry().unwrap().is_none());
+
+        let mut reader = ZipStreamReader::new(&buffer[..]);
+        assert!(reader.next_entry().is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! WinZip AES encryption, in its AE-1 and AE-2 variants.
+//!
+//! The contents are encrypted with AES in CTR mode, using a key derived from
+//! the password and a random salt with PBKDF2-HMAC-SHA1. The encrypted
+//! contents are preceded by the salt and a 2 byte password verifier, and
+//! followed by a 10 byte HMAC-SHA1 authentication code of the encrypted
+//! contents.
+//!
+//! The real compression method is stored in the [`WinZipAesExtraField`], as
+//! the one in the headers is always 99. AE-2 also leaves the CRC-32 zeroed,
+//! as it would leak information about the plaintext.
+//!
+//! [`WinZipAesExtraField`]: crate::WinZipAesExtraField
+
+use std::io::{self, BufRead, Read};
+
+use aes::{Aes128, Aes192, Aes256};
+use ctr::{
+    cipher::{KeyIvInit, StreamCipher},
+    Ctr128LE,
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::{bits::invalid_data, ZipParseError};
+
+const PBKDF2_ITERATIONS: u32 = 1000;
+
+pub(crate) const VERIFIER_LEN: usize = 2;
+pub(crate) const AUTHENTICATION_CODE_LEN: usize = 10;
+
+/// The vendor version of AE-2, which doesn't store the CRC-32
+pub(crate) const AE_2: u16 = 2;
+
+/// The key size of WinZip AES encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AesStrength {
+    Aes128 = 1,
+    Aes192 = 2,
+    Aes256 = 3,
+}
+
+impl AesStrength {
+    /// Parse the strength stored in [`crate::WinZipAesExtraField::strength`]
+    pub fn from_u8(n: u8) -> Option<AesStrength> {
+        match n {
+            1 => Some(AesStrength::Aes128),
+            2 => Some(AesStrength::Aes192),
+            3 => Some(AesStrength::Aes256),
+            _ => None,
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            AesStrength::Aes128 => 16,
+            AesStrength::Aes192 => 24,
+            AesStrength::Aes256 => 32,
+        }
+    }
+
+    pub(crate) fn salt_len(self) -> usize {
+        self.key_len() / 2
+    }
+}
+
+/// The cipher and authentication code of a single file, derived from the
+/// password and salt
+pub(crate) struct Encryption {
+    cipher: Box<dyn StreamCipher + Send + Sync>,
+    mac: Hmac<Sha1>,
+}
+
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encryption").finish_non_exhaustive()
+    }
+}
+
+impl Encryption {
+    /// Derive the keys for `password` and `salt`, returning them along with
+    /// the password verifier
+    pub(crate) fn new(
+        password: &[u8],
+        salt: &[u8],
+        strength: AesStrength,
+    ) -> (Self, [u8; VERIFIER_LEN]) {
+        let key_len = strength.key_len();
+
+        let mut derived = vec![0; 2 * key_len + VERIFIER_LEN];
+        pbkdf2::pbkdf2_hmac::<Sha1>(password, salt, PBKDF2_ITERATIONS, &mut derived);
+
+        let (key, rest) = derived.split_at(key_len);
+        let (mac_key, verifier) = rest.split_at(key_len);
+
+        // the counter is little endian and starts at 1
+        let mut iv = [0; 16];
+        iv[0] = 1;
+
+        let cipher: Box<dyn StreamCipher + Send + Sync> = match strength {
+            AesStrength::Aes128 => Box::new(Ctr128LE::<Aes128>::new(key.into(), &iv.into())),
+            AesStrength::Aes192 => Box::new(Ctr128LE::<Aes192>::new(key.into(), &iv.into())),
+            AesStrength::Aes256 => Box::new(Ctr128LE::<Aes256>::new(key.into(), &iv.into())),
+        };
+
+        let mac = Hmac::<Sha1>::new_from_slice(mac_key).expect("hmac accepts any key length");
+
+        (Self { cipher, mac }, [verifier[0], verifier[1]])
+    }
+
+    pub(crate) fn encrypt(&mut self, bytes: &mut [u8]) {
+        self.cipher.apply_keystream(bytes);
+        self.mac.update(bytes);
+    }
+
+    fn decrypt(&mut self, bytes: &mut [u8]) {
+        self.mac.update(bytes);
+        self.cipher.apply_keystream(bytes);
+    }
+
+    /// The authentication code of everything encrypted or decrypted
+    pub(crate) fn authentication_code(self) -> [u8; AUTHENTICATION_CODE_LEN] {
+        let mut code = [0; AUTHENTICATION_CODE_LEN];
+        code.copy_from_slice(&self.mac.finalize().into_bytes()[..AUTHENTICATION_CODE_LEN]);
+        code
+    }
+}
+
+/// Decrypts the contents of a file as they are read, checking the
+/// authentication code before the last of them is returned
+#[derive(Debug)]
+pub(crate) struct AesReader<R> {
+    inner: R,
+    /// `None` once the authentication code has been checked
+    encryption: Option<Encryption>,
+    /// Encrypted bytes not yet taken from `inner`
+    remaining: u64,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> AesReader<R> {
+    /// Read the salt and password verifier preceding `compressed_size` bytes
+    /// of encrypted contents, returning [`ZipParseError::InvalidPassword`] if
+    /// the verifier doesn't match
+    pub(crate) fn new(
+        mut inner: R,
+        password: &[u8],
+        strength: AesStrength,
+        compressed_size: u64,
+    ) -> Result<Self, ZipParseError> {
+        let overhead = strength.salt_len() + VERIFIER_LEN + AUTHENTICATION_CODE_LEN;
+        let remaining = compressed_size
+            .checked_sub(overhead as u64)
+            .ok_or(ZipParseError::Generic("aes encrypted file too short"))?;
+
+        let mut salt = vec![0; strength.salt_len()];
+        inner.read_exact(&mut salt)?;
+
+        let mut verifier = [0; VERIFIER_LEN];
+        inner.read_exact(&mut verifier)?;
+
+        let (encryption, expected) = Encryption::new(password, &salt, strength);
+
+        if verifier != expected {
+            return Err(ZipParseError::InvalidPassword);
+        }
+
+        Ok(Self {
+            inner,
+            encryption: Some(encryption),
+            remaining,
+            buffer: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    fn authenticate(&mut self, encryption: Encryption) -> io::Result<()> {
+        let mut code = [0; AUTHENTICATION_CODE_LEN];
+        self.inner.read_exact(&mut code)?;
+
+        if encryption.mac.verify_truncated_left(&code).is_err() {
+            return Err(invalid_data("aes authentication code mismatch"));
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for AesReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for AesReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos < self.buffer.len() {
+            return Ok(&self.buffer[self.pos..]);
+        }
+
+        let Some(encryption) = self.encryption.as_mut() else {
+            return Ok(&[]);
+        };
+
+        self.buffer.clear();
+        self.pos = 0;
+
+        if self.remaining > 0 {
+            let available = self.inner.fill_buf()?;
+
+            if available.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            let n = available
+                .len()
+                .min(self.remaining.min(usize::MAX as u64) as usize);
+
+            self.buffer.extend_from_slice(&available[..n]);
+            self.inner.consume(n);
+            self.remaining -= n as u64;
+
+            encryption.decrypt(&mut self.buffer);
+        }
+
+        // the size is known, so the authentication code can be checked before
+        // the last of the plaintext is handed out
+        if let Some(encryption) = self.encryption.take_if(|_| self.remaining == 0) {
+            if let Err(err) = self.authenticate(encryption) {
+                self.buffer.clear();
+                return Err(err);
+            }
+        }
+
+        Ok(&self.buffer)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buffer.len());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use crate::ZipParseError;
+
+    use super::{AesReader, AesStrength, Encryption};
+
+    /// Encrypt `contents` as they are stored in an archive
+    fn encrypt(contents: &[u8], password: &[u8], strength: AesStrength) -> Vec<u8> {
+        let salt = vec![7; strength.salt_len()];
+        let (mut encryption, verifier) = Encryption::new(password, &salt, strength);
+
+        let mut encrypted = contents.to_vec();
+        encryption.encrypt(&mut encrypted);
+
+        let mut out = salt;
+        out.extend(verifier);
+        out.extend(encrypted);
+        out.extend(encryption.authentication_code());
+        out
+    }
+
+    #[test]
+    fn decrypt() {
+        let contents = b"hello world\n".repeat(100);
+
+        for strength in [
+            AesStrength::Aes128,
+            AesStrength::Aes192,
+            AesStrength::Aes256,
+        ] {
+            let encrypted = encrypt(&contents, b"secret", strength);
+            let size = encrypted.len() as u64;
+
+            let mut decrypted = Vec::new();
+            AesReader::new(&encrypted[..], b"secret", strength, size)
+                .unwrap()
+                .read_to_end(&mut decrypted)
+                .unwrap();
+            assert_eq!(decrypted, contents);
+
+            assert!(matches!(
+                AesReader::new(&encrypted[..], b"wrong", strength, size),
+                Err(ZipParseError::InvalidPassword)
+            ));
+
+            // flipping a bit of the contents is caught by the authentication
+            // code
+            let mut tampered = encrypted.clone();
+            tampered[100] ^= 1;
+
+            let mut reader = AesReader::new(&tampered[..], b"secret", strength, size).unwrap();
+            assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+}
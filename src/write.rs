@@ -0,0 +1,1391 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crc32fast::Hasher;
+use flate2::{write::DeflateEncoder, Compression};
+
+#[cfg(feature = "aes")]
+use crate::winzip_aes::{self, AesStrength, Encryption};
+use crate::{common::*, ZipParseError};
+
+const VERSION_STORED: u16 = 10;
+const VERSION_DEFLATE: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+#[cfg(feature = "aes")]
+const VERSION_AES: u16 = 51;
+const VERSION_ZSTD_XZ: u16 = 63;
+
+/// The version of the ZIP specification written in "version made by"
+const ZIP_SPECIFICATION_VERSION: u8 = 45;
+
+const ZIP64_EXTRA_FIELD_LEN: u16 = 16;
+
+/// Regular file with `rw-r--r--` permissions
+const DEFAULT_FILE_MODE: u32 = 0o100644;
+
+/// Directory with `rwxr-xr-x` permissions
+const DEFAULT_DIRECTORY_MODE: u32 = 0o040755;
+
+/// MS-DOS directory attribute
+const DOS_DIRECTORY_ATTRIBUTE: u32 = 0x10;
+
+/// Options for a single file added to a [`ZipWriter`]
+#[derive(Debug, Clone)]
+pub struct FileOptions {
+    compression_method: CompressionMethod,
+    compression_level: Option<u32>,
+    date_time_modified: DateTimeModified,
+    os: Os,
+    external_attributes: Option<ExternalAttributes>,
+    comment: Vec<u8>,
+    large_file: bool,
+    #[cfg(feature = "aes")]
+    encryption: Option<(Vec<u8>, AesStrength)>,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            compression_method: CompressionMethod::DEFLATE,
+            compression_level: None,
+            date_time_modified: DateTimeModified::default(),
+            os: Os(OsName::Unix as u8),
+            external_attributes: None,
+            comment: Vec::new(),
+            large_file: false,
+            #[cfg(feature = "aes")]
+            encryption: None,
+        }
+    }
+}
+
+impl FileOptions {
+    /// The algorithm used to compress this file. Defaults to
+    /// [`CompressionMethod::DEFLATE`].
+    pub fn compression_method(mut self, method: CompressionMethod) -> Self {
+        self.compression_method = method;
+        self
+    }
+
+    /// The compression level passed to the encoder. The meaning and range of
+    /// this value depend on the compression method.
+    ///
+    /// Deflate accepts 0 through 9, Zstandard 1 through 22 and XZ presets 0
+    /// through 9.
+    pub fn compression_level(mut self, level: u32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    pub fn date_time_modified(mut self, date_time_modified: DateTimeModified) -> Self {
+        self.date_time_modified = date_time_modified;
+        self
+    }
+
+    /// The host system the file attributes are interpreted for. Defaults to
+    /// [`OsName::Unix`].
+    pub fn os(mut self, os: Os) -> Self {
+        self.os = os;
+        self
+    }
+
+    /// Defaults to a regular file (or directory) with typical Unix permissions
+    pub fn external_attributes(mut self, external_attributes: ExternalAttributes) -> Self {
+        self.external_attributes = Some(external_attributes);
+        self
+    }
+
+    /// A comment stored in the central directory for this file
+    pub fn comment(mut self, comment: impl Into<Vec<u8>>) -> Self {
+        self.comment = comment.into();
+        self
+    }
+
+    /// Reserve space for zip64 sizes in the local file header.
+    ///
+    /// This must be set for files with more than 4 GiB of contents, and
+    /// should otherwise be left unset, as it adds 20 bytes to the header and
+    /// requires readers to support zip64.
+    pub fn large_file(mut self, large_file: bool) -> Self {
+        self.large_file = large_file;
+        self
+    }
+
+    /// Encrypt the contents with WinZip AES, using a key derived from
+    /// `password`.
+    ///
+    /// Files are written as AE-2, which leaves the CRC-32 zeroed so that it
+    /// can't leak information about the contents. They are authenticated by
+    /// an HMAC-SHA1 code instead.
+    #[cfg(feature = "aes")]
+    pub fn aes_encryption(mut self, password: impl Into<Vec<u8>>, strength: AesStrength) -> Self {
+        self.encryption = Some((password.into(), strength));
+        self
+    }
+}
+
+/// Create a new ZIP archive
+///
+/// ```no_run
+/// # use std::io::Write;
+/// # use zip_rs::{FileOptions, ZipWriter};
+/// let file = std::fs::File::create("./foo.zip").unwrap();
+/// let mut writer = ZipWriter::new(file).unwrap();
+///
+/// writer.start_file("hello.txt", FileOptions::default()).unwrap();
+/// writer.write_all(b"hello world").unwrap();
+///
+/// writer.finish().unwrap();
+/// ```
+///
+/// [`ZipWriter::finish`] must be called to write the central directory. An
+/// archive which is dropped without being finished is not valid.
+///
+/// See [`ZipStreamWriter`] for writing to sinks which cannot seek.
+#[derive(Debug)]
+pub struct ZipWriter<W: Write + Seek> {
+    core: WriterCore<W>,
+}
+
+impl<W: Write + Seek> ZipWriter<W> {
+    /// Create a writer which begins writing at the current position of `inner`
+    pub fn new(mut inner: W) -> Result<Self, ZipParseError> {
+        let position = inner.stream_position()?;
+
+        Ok(Self {
+            core: WriterCore::new(inner, position),
+        })
+    }
+
+    /// Set the comment for the entire archive
+    pub fn set_comment(&mut self, comment: impl Into<Vec<u8>>) {
+        self.core.comment = comment.into();
+    }
+
+    /// Begin a new file. The contents of the file are written through this
+    /// writer's [`Write`] implementation.
+    ///
+    /// Any file currently being written is finished first.
+    pub fn start_file(
+        &mut self,
+        name: impl AsRef<[u8]>,
+        options: FileOptions,
+    ) -> Result<(), ZipParseError> {
+        self.finish_file()?;
+        self.core.start_file(name.as_ref(), options, false)
+    }
+
+    /// Add an empty directory entry. A trailing `/` is appended to `name` if
+    /// it is not already present.
+    pub fn add_directory(
+        &mut self,
+        name: impl AsRef<[u8]>,
+        options: FileOptions,
+    ) -> Result<(), ZipParseError> {
+        let (name, options) = directory_entry(name.as_ref(), options);
+        self.start_file(name, options)
+    }
+
+    /// Finish the file currently being written, if any, and go back to fill in
+    /// its crc and sizes
+    fn finish_file(&mut self) -> Result<(), ZipParseError> {
+        let mut inner = match self.core.finish_contents()? {
+            Some(inner) => inner,
+            None => return Ok(()),
+        };
+
+        let end = inner.count;
+        let entry = self.core.files.last().unwrap();
+
+        if !entry.large_file && entry.needs_zip64_sizes() {
+            return Err(ZipParseError::FileTooLarge(entry.uncompressed_size));
+        }
+
+        let w = &mut inner.inner;
+
+        // skip the signature, version needed, flags, method and date
+        w.seek(SeekFrom::Start(entry.local_header_offset + 14))?;
+        w.write_all(&entry.crc.to_le_bytes())?;
+
+        if entry.large_file {
+            // skip the saturated sizes, name and extra field header
+            w.seek(SeekFrom::Current(12 + entry.name.len() as i64 + 4))?;
+            w.write_all(&entry.uncompressed_size.to_le_bytes())?;
+            w.write_all(&entry.compressed_size.to_le_bytes())?;
+        } else {
+            w.write_all(&(entry.compressed_size as u32).to_le_bytes())?;
+            w.write_all(&(entry.uncompressed_size as u32).to_le_bytes())?;
+        }
+
+        w.seek(SeekFrom::Start(end))?;
+
+        self.core.sink = Sink::Stored(inner);
+
+        Ok(())
+    }
+
+    /// Finish the last file and write the central directory, returning the
+    /// underlying writer
+    pub fn finish(mut self) -> Result<W, ZipParseError> {
+        self.finish_file()?;
+        self.core.finish()
+    }
+}
+
+impl<W: Write + Seek> Write for ZipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.core.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.core.flush()
+    }
+}
+
+/// Create a new ZIP archive without seeking, such as when writing to a pipe or
+/// socket
+///
+/// Because the local file header is written before the contents are known,
+/// each file's crc and sizes are instead written in a data descriptor
+/// following its contents. Files larger than 4 GiB automatically use zip64
+/// data descriptors, so unlike with [`ZipWriter`], they don't need
+/// [`FileOptions::large_file`].
+///
+/// [`ZipStreamWriter::finish`] must be called to write the central directory.
+/// An archive which is dropped without being finished is not valid.
+#[derive(Debug)]
+pub struct ZipStreamWriter<W: Write> {
+    core: WriterCore<W>,
+}
+
+impl<W: Write> ZipStreamWriter<W> {
+    /// Create a writer which treats the first byte written to `inner` as the
+    /// start of the archive
+    pub fn new(inner: W) -> Self {
+        Self {
+            core: WriterCore::new(inner, 0),
+        }
+    }
+
+    /// Set the comment for the entire archive
+    pub fn set_comment(&mut self, comment: impl Into<Vec<u8>>) {
+        self.core.comment = comment.into();
+    }
+
+    /// Begin a new file. The contents of the file are written through this
+    /// writer's [`Write`] implementation.
+    ///
+    /// Any file currently being written is finished first.
+    pub fn start_file(
+        &mut self,
+        name: impl AsRef<[u8]>,
+        options: FileOptions,
+    ) -> Result<(), ZipParseError> {
+        self.finish_file()?;
+        self.core.start_file(name.as_ref(), options, true)
+    }
+
+    /// Add an empty directory entry. A trailing `/` is appended to `name` if
+    /// it is not already present.
+    pub fn add_directory(
+        &mut self,
+        name: impl AsRef<[u8]>,
+        options: FileOptions,
+    ) -> Result<(), ZipParseError> {
+        let (name, options) = directory_entry(name.as_ref(), options);
+        self.start_file(name, options)
+    }
+
+    /// Finish the file currently being written, if any, and write its data
+    /// descriptor
+    fn finish_file(&mut self) -> Result<(), ZipParseError> {
+        let mut inner = match self.core.finish_contents()? {
+            Some(inner) => inner,
+            None => return Ok(()),
+        };
+
+        let entry = self.core.files.last().unwrap();
+
+        inner.write_all(&DATA_DESCRIPTOR_SIGNATURE)?;
+        inner.write_all(&entry.crc.to_le_bytes())?;
+
+        // streaming readers recognize zip64 descriptors either by the zip64
+        // extra field of a large file's local header, or by having read more
+        // than 4 GiB of contents, as with Info-ZIP
+        if entry.large_file || entry.needs_zip64_sizes() {
+            inner.write_all(&entry.compressed_size.to_le_bytes())?;
+            inner.write_all(&entry.uncompressed_size.to_le_bytes())?;
+        } else {
+            inner.write_all(&(entry.compressed_size as u32).to_le_bytes())?;
+            inner.write_all(&(entry.uncompressed_size as u32).to_le_bytes())?;
+        }
+
+        self.core.sink = Sink::Stored(inner);
+
+        Ok(())
+    }
+
+    /// Finish the last file and write the central directory, returning the
+    /// underlying writer
+    pub fn finish(mut self) -> Result<W, ZipParseError> {
+        self.finish_file()?;
+        self.core.finish()
+    }
+}
+
+impl<W: Write> Write for ZipStreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.core.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.core.flush()
+    }
+}
+
+/// The name and options of a directory entry
+fn directory_entry(name: &[u8], options: FileOptions) -> (Vec<u8>, FileOptions) {
+    let mut name = name.to_vec();
+
+    if !name.ends_with(b"/") {
+        name.push(b'/');
+    }
+
+    let external_attributes = options.external_attributes.unwrap_or(ExternalAttributes(
+        (DEFAULT_DIRECTORY_MODE << 16) | DOS_DIRECTORY_ATTRIBUTE,
+    ));
+
+    let options = options
+        .compression_method(CompressionMethod::STORED)
+        .external_attributes(external_attributes);
+
+    // directories have no contents to encrypt
+    #[cfg(feature = "aes")]
+    let options = FileOptions {
+        encryption: None,
+        ..options
+    };
+
+    (name, options)
+}
+
+/// State shared between [`ZipWriter`] and [`ZipStreamWriter`]
+#[derive(Debug)]
+struct WriterCore<W: Write> {
+    sink: Sink<W>,
+    files: Vec<FileEntry>,
+    current: Option<CurrentFile>,
+    comment: Vec<u8>,
+}
+
+impl<W: Write> WriterCore<W> {
+    fn new(inner: W, position: u64) -> Self {
+        Self {
+            sink: Sink::Stored(CountingWriter::new(inner, position)),
+            files: Vec::new(),
+            current: None,
+            comment: Vec::new(),
+        }
+    }
+
+    /// Write the local file header and prepare the encoder. The previous
+    /// file must already be finished.
+    fn start_file(
+        &mut self,
+        name: &[u8],
+        options: FileOptions,
+        streaming: bool,
+    ) -> Result<(), ZipParseError> {
+        let external_attributes = options
+            .external_attributes
+            .unwrap_or(ExternalAttributes(DEFAULT_FILE_MODE << 16));
+
+        let mut entry = FileEntry::new(name, external_attributes, &options)?;
+
+        if streaming {
+            entry.flags.0 |= ZipFlags::DATA_DESCRIPTOR;
+        }
+
+        // generate the salt up front, so that nothing is written if it fails
+        #[cfg(feature = "aes")]
+        let encryption = options
+            .encryption
+            .as_ref()
+            .map(|(password, strength)| EncryptionHeader::new(password, *strength))
+            .transpose()?;
+
+        let mut inner = self.sink.take().finish()?;
+
+        write_local_header(&mut inner, &mut entry)?;
+
+        let data_start = inner.count;
+
+        #[cfg(feature = "aes")]
+        if let Some(encryption) = encryption {
+            inner.start_encryption(encryption)?;
+        }
+
+        self.sink = Sink::new(inner, &options)?;
+        self.current = Some(CurrentFile {
+            hasher: Hasher::new(),
+            uncompressed_size: 0,
+            data_start,
+        });
+        self.files.push(entry);
+
+        Ok(())
+    }
+
+    /// Flush the encoder of the file currently being written, if any, and
+    /// record its crc and sizes.
+    ///
+    /// The underlying writer is handed back so the caller can record the crc
+    /// and sizes in the archive itself, after which it must be restored to
+    /// `self.sink`.
+    fn finish_contents(&mut self) -> Result<Option<CountingWriter<W>>, ZipParseError> {
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(None),
+        };
+
+        let inner = self.sink.take().finish()?;
+
+        let entry = self.files.last_mut().unwrap();
+
+        // AE-2 leaves the crc zeroed
+        entry.crc = if entry.compression_method == CompressionMethod::AES {
+            0
+        } else {
+            current.hasher.finalize()
+        };
+        entry.uncompressed_size = current.uncompressed_size;
+        entry.compressed_size = inner.count - current.data_start;
+
+        Ok(Some(inner))
+    }
+
+    fn finish(mut self) -> Result<W, ZipParseError> {
+        let mut inner = self.sink.take().finish()?;
+
+        write_central_directory(&mut inner, &self.files, &self.comment)?;
+
+        inner.inner.flush()?;
+
+        Ok(inner.inner)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let current = self
+            .current
+            .as_mut()
+            .ok_or_else(|| io::Error::other("no file has been started"))?;
+
+        let n = self.sink.write(buf)?;
+
+        current.hasher.update(&buf[..n]);
+        current.uncompressed_size += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// Bookkeeping for the file whose contents are currently being written
+#[derive(Debug)]
+struct CurrentFile {
+    hasher: Hasher,
+    uncompressed_size: u64,
+    /// Position immediately after the local file header
+    data_start: u64,
+}
+
+/// Everything needed to write a central directory file header
+#[derive(Debug)]
+struct FileEntry {
+    name: Vec<u8>,
+    comment: Vec<u8>,
+    flags: ZipFlags,
+    compression_method: CompressionMethod,
+    date_time_modified: DateTimeModified,
+    os: Os,
+    external_attributes: ExternalAttributes,
+    version_needed: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: u64,
+    large_file: bool,
+    /// Extra field records written to both the local and central headers,
+    /// after any zip64 record
+    extra_field: Vec<u8>,
+}
+
+impl FileEntry {
+    fn new(
+        name: &[u8],
+        external_attributes: ExternalAttributes,
+        options: &FileOptions,
+    ) -> Result<Self, ZipParseError> {
+        if name.len() > usize::from(u16::MAX) {
+            return Err(ZipParseError::Generic("file name too long"));
+        }
+
+        if options.comment.len() > usize::from(u16::MAX) {
+            return Err(ZipParseError::Generic("file comment too long"));
+        }
+
+        if !encoder_available(options.compression_method) {
+            return Err(ZipParseError::UnsupportedCompression(
+                options.compression_method,
+            ));
+        }
+
+        let mut flags = 0;
+
+        if !name.is_ascii() && std::str::from_utf8(name).is_ok() {
+            flags |= ZipFlags::LANGUAGE_ENCODING;
+        }
+
+        let version_needed = match options.compression_method.name() {
+            CompressionMethodName::Zstd | CompressionMethodName::Xz => VERSION_ZSTD_XZ,
+            _ if options.large_file => VERSION_ZIP64,
+            CompressionMethodName::None => VERSION_STORED,
+            _ => VERSION_DEFLATE,
+        };
+
+        let entry = Self {
+            name: name.to_vec(),
+            comment: options.comment.clone(),
+            flags: ZipFlags(flags),
+            compression_method: options.compression_method,
+            date_time_modified: options.date_time_modified,
+            os: options.os,
+            external_attributes,
+            version_needed,
+            crc: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            local_header_offset: 0,
+            large_file: options.large_file,
+            extra_field: Vec::new(),
+        };
+
+        // the real compression method moves to the extra field
+        #[cfg(feature = "aes")]
+        if let Some((_, strength)) = options.encryption {
+            return Ok(Self {
+                flags: ZipFlags(entry.flags.0 | ZipFlags::ENCRYPTED_FILE),
+                compression_method: CompressionMethod::AES,
+                version_needed: entry.version_needed.max(VERSION_AES),
+                extra_field: winzip_aes_extra_field(strength, options.compression_method),
+                ..entry
+            });
+        }
+
+        Ok(entry)
+    }
+
+    fn needs_zip64_sizes(&self) -> bool {
+        self.uncompressed_size >= u64::from(u32::MAX) || self.compressed_size >= u64::from(u32::MAX)
+    }
+}
+
+/// The WinZip AES extra field record of an AE-2 encrypted file
+#[cfg(feature = "aes")]
+fn winzip_aes_extra_field(strength: AesStrength, method: CompressionMethod) -> Vec<u8> {
+    let mut record = Vec::with_capacity(11);
+
+    record.extend_from_slice(&WINZIP_AES_EXTRA_FIELD_ID.to_le_bytes());
+    record.extend_from_slice(&7u16.to_le_bytes());
+    record.extend_from_slice(&winzip_aes::AE_2.to_le_bytes());
+    record.extend_from_slice(b"AE");
+    record.push(strength as u8);
+    record.extend_from_slice(&method.0.to_le_bytes());
+
+    record
+}
+
+/// Tracks the absolute position in the underlying writer, encrypting what is
+/// written while `encryption` is set
+#[derive(Debug)]
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+    #[cfg(feature = "aes")]
+    encryption: Option<Encryption>,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W, count: u64) -> Self {
+        Self {
+            inner,
+            count,
+            #[cfg(feature = "aes")]
+            encryption: None,
+        }
+    }
+
+    /// Write the salt and password verifier of a WinZip AES encrypted file,
+    /// and encrypt everything written from then on
+    #[cfg(feature = "aes")]
+    fn start_encryption(&mut self, header: EncryptionHeader) -> io::Result<()> {
+        self.write_all(&header.salt_and_verifier)?;
+        self.encryption = Some(header.encryption);
+
+        Ok(())
+    }
+
+    /// Write the authentication code of the encrypted contents, if any
+    fn finish_encryption(&mut self) -> Result<(), ZipParseError> {
+        #[cfg(feature = "aes")]
+        if let Some(encryption) = self.encryption.take() {
+            self.write_all(&encryption.authentication_code())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The salt and password verifier which precede the contents of a WinZip AES
+/// encrypted file, and the keys derived along with them
+#[cfg(feature = "aes")]
+struct EncryptionHeader {
+    salt_and_verifier: Vec<u8>,
+    encryption: Encryption,
+}
+
+#[cfg(feature = "aes")]
+impl EncryptionHeader {
+    fn new(password: &[u8], strength: AesStrength) -> Result<Self, ZipParseError> {
+        let mut salt = vec![0; strength.salt_len()];
+        getrandom::getrandom(&mut salt).map_err(io::Error::from)?;
+
+        let (encryption, verifier) = Encryption::new(password, &salt, strength);
+        salt.extend_from_slice(&verifier);
+
+        Ok(Self {
+            salt_and_verifier: salt,
+            encryption,
+        })
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the keystream can't be rewound, so everything encrypted must be
+        // written
+        #[cfg(feature = "aes")]
+        if let Some(encryption) = &mut self.encryption {
+            let mut encrypted = buf.to_vec();
+            encryption.encrypt(&mut encrypted);
+
+            self.inner.write_all(&encrypted)?;
+            self.count += buf.len() as u64;
+
+            return Ok(buf.len());
+        }
+
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether [`Sink::new`] has an encoder for `method`
+fn encoder_available(method: CompressionMethod) -> bool {
+    match method.name() {
+        CompressionMethodName::None | CompressionMethodName::Deflate => true,
+        #[cfg(feature = "zstd")]
+        CompressionMethodName::Zstd => true,
+        #[cfg(feature = "xz")]
+        CompressionMethodName::Xz => true,
+        _ => false,
+    }
+}
+
+/// The encoder for the file currently being written
+enum Sink<W: Write> {
+    Stored(CountingWriter<W>),
+    Deflate(DeflateEncoder<CountingWriter<W>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, CountingWriter<W>>),
+    #[cfg(feature = "xz")]
+    Xz(Box<lzma_rust2::XzWriter<CountingWriter<W>>>),
+    /// Placeholder while switching between encoders
+    Closed,
+}
+
+impl<W: Write> std::fmt::Debug for Sink<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Sink::Stored(..) => "Stored",
+            Sink::Deflate(..) => "Deflate",
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(..) => "Zstd",
+            #[cfg(feature = "xz")]
+            Sink::Xz(..) => "Xz",
+            Sink::Closed => "Closed",
+        })
+    }
+}
+
+impl<W: Write> Sink<W> {
+    fn new(inner: CountingWriter<W>, options: &FileOptions) -> Result<Self, ZipParseError> {
+        match options.compression_method.name() {
+            CompressionMethodName::None => Ok(Sink::Stored(inner)),
+            CompressionMethodName::Deflate => {
+                let level = options
+                    .compression_level
+                    .map_or_else(Compression::default, Compression::new);
+
+                Ok(Sink::Deflate(DeflateEncoder::new(inner, level)))
+            }
+            #[cfg(feature = "zstd")]
+            CompressionMethodName::Zstd => {
+                let level = options
+                    .compression_level
+                    .map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |level| level as i32);
+
+                Ok(Sink::Zstd(zstd::stream::write::Encoder::new(inner, level)?))
+            }
+            #[cfg(feature = "xz")]
+            CompressionMethodName::Xz => {
+                let preset = options.compression_level.unwrap_or(6).min(9);
+                let options = lzma_rust2::XzOptions::with_preset(preset);
+
+                Ok(Sink::Xz(Box::new(lzma_rust2::XzWriter::new(
+                    inner, options,
+                )?)))
+            }
+            _ => Err(ZipParseError::UnsupportedCompression(
+                options.compression_method,
+            )),
+        }
+    }
+
+    fn take(&mut self) -> Self {
+        std::mem::replace(self, Sink::Closed)
+    }
+
+    /// Flush any buffered compressed data and return the underlying writer
+    fn finish(self) -> Result<CountingWriter<W>, ZipParseError> {
+        let mut w = match self {
+            Sink::Stored(w) => w,
+            Sink::Deflate(w) => w.finish()?,
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(w) => w.finish()?,
+            #[cfg(feature = "xz")]
+            Sink::Xz(w) => (*w).finish()?,
+            Sink::Closed => return Err(ZipParseError::Generic("writer is closed")),
+        };
+
+        w.finish_encryption()?;
+
+        Ok(w)
+    }
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Stored(w) => w.write(buf),
+            Sink::Deflate(w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(w) => w.write(buf),
+            #[cfg(feature = "xz")]
+            Sink::Xz(w) => w.write(buf),
+            Sink::Closed => Err(io::Error::other("writer is closed")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Stored(w) => w.flush(),
+            Sink::Deflate(w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(w) => w.flush(),
+            #[cfg(feature = "xz")]
+            Sink::Xz(w) => w.flush(),
+            Sink::Closed => Ok(()),
+        }
+    }
+}
+
+fn write_local_header<W: Write>(
+    w: &mut CountingWriter<W>,
+    entry: &mut FileEntry,
+) -> Result<(), ZipParseError> {
+    entry.local_header_offset = w.count;
+
+    let (sizes, zip64_extra_field_len) = if entry.large_file {
+        (u32::MAX, ZIP64_EXTRA_FIELD_LEN + 4)
+    } else {
+        (0, 0)
+    };
+
+    let extra_field_len = zip64_extra_field_len + entry.extra_field.len() as u16;
+
+    w.write_all(&LOCAL_FILE_SIGNATURE)?;
+    w.write_all(&entry.version_needed.to_le_bytes())?;
+    w.write_all(&entry.flags.0.to_le_bytes())?;
+    w.write_all(&entry.compression_method.0.to_le_bytes())?;
+    w.write_all(&entry.date_time_modified.to_u32().to_le_bytes())?;
+    w.write_all(&entry.crc.to_le_bytes())?;
+    w.write_all(&sizes.to_le_bytes())?;
+    w.write_all(&sizes.to_le_bytes())?;
+    w.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+    w.write_all(&extra_field_len.to_le_bytes())?;
+    w.write_all(&entry.name)?;
+
+    if entry.large_file {
+        w.write_all(&ZIP64_EXTRA_FIELD_ID.to_le_bytes())?;
+        w.write_all(&ZIP64_EXTRA_FIELD_LEN.to_le_bytes())?;
+        w.write_all(&entry.uncompressed_size.to_le_bytes())?;
+        w.write_all(&entry.compressed_size.to_le_bytes())?;
+    }
+
+    w.write_all(&entry.extra_field)?;
+
+    Ok(())
+}
+
+fn write_central_directory_header<W: Write>(
+    w: &mut CountingWriter<W>,
+    entry: &FileEntry,
+) -> Result<(), ZipParseError> {
+    // only the fields which overflow are stored in the zip64 extra field, in
+    // this fixed order
+    let mut zip64_extra_field = Vec::new();
+
+    let uncompressed_size = saturate(entry.uncompressed_size, &mut zip64_extra_field);
+    let compressed_size = saturate(entry.compressed_size, &mut zip64_extra_field);
+    let local_header_offset = saturate(entry.local_header_offset, &mut zip64_extra_field);
+
+    let version_needed = if zip64_extra_field.is_empty() {
+        entry.version_needed
+    } else {
+        VERSION_ZIP64
+    };
+
+    let zip64_extra_field_len = if zip64_extra_field.is_empty() {
+        0
+    } else {
+        zip64_extra_field.len() as u16 + 4
+    };
+
+    let extra_field_len = zip64_extra_field_len + entry.extra_field.len() as u16;
+
+    w.write_all(&CENTRAL_DIRECTORY_FILE_SIGNATURE)?;
+    w.write_all(&[ZIP_SPECIFICATION_VERSION, entry.os.0])?;
+    w.write_all(&version_needed.to_le_bytes())?;
+    w.write_all(&entry.flags.0.to_le_bytes())?;
+    w.write_all(&entry.compression_method.0.to_le_bytes())?;
+    w.write_all(&entry.date_time_modified.to_u32().to_le_bytes())?;
+    w.write_all(&entry.crc.to_le_bytes())?;
+    w.write_all(&compressed_size.to_le_bytes())?;
+    w.write_all(&uncompressed_size.to_le_bytes())?;
+    w.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+    w.write_all(&extra_field_len.to_le_bytes())?;
+    w.write_all(&(entry.comment.len() as u16).to_le_bytes())?;
+    // disk number start
+    w.write_all(&0u16.to_le_bytes())?;
+    // internal attributes
+    w.write_all(&0u16.to_le_bytes())?;
+    w.write_all(&entry.external_attributes.0.to_le_bytes())?;
+    w.write_all(&local_header_offset.to_le_bytes())?;
+    w.write_all(&entry.name)?;
+
+    if !zip64_extra_field.is_empty() {
+        w.write_all(&ZIP64_EXTRA_FIELD_ID.to_le_bytes())?;
+        w.write_all(&(zip64_extra_field.len() as u16).to_le_bytes())?;
+        w.write_all(&zip64_extra_field)?;
+    }
+
+    w.write_all(&entry.extra_field)?;
+    w.write_all(&entry.comment)?;
+
+    Ok(())
+}
+
+/// Returns `u32::MAX` and appends the full value to the zip64 extra field if
+/// `n` does not fit in 32 bits
+fn saturate(n: u64, zip64_extra_field: &mut Vec<u8>) -> u32 {
+    if n >= u64::from(u32::MAX) {
+        zip64_extra_field.extend_from_slice(&n.to_le_bytes());
+        u32::MAX
+    } else {
+        n as u32
+    }
+}
+
+fn write_central_directory<W: Write>(
+    w: &mut CountingWriter<W>,
+    files: &[FileEntry],
+    comment: &[u8],
+) -> Result<(), ZipParseError> {
+    if comment.len() > usize::from(u16::MAX) {
+        return Err(ZipParseError::Generic("archive comment too long"));
+    }
+
+    let central_dir_offset = w.count;
+
+    for entry in files {
+        write_central_directory_header(w, entry)?;
+    }
+
+    let central_dir_size = w.count - central_dir_offset;
+    let total_entries = files.len() as u64;
+
+    let is_zip64 = total_entries >= u64::from(u16::MAX)
+        || central_dir_size >= u64::from(u32::MAX)
+        || central_dir_offset >= u64::from(u32::MAX);
+
+    if is_zip64 {
+        let zip64_end_offset = w.count;
+
+        w.write_all(&ZIP64_END_CENTRAL_DIRECTORY_SIGNATURE)?;
+        // size of the remaining record
+        w.write_all(&44u64.to_le_bytes())?;
+        w.write_all(&[ZIP_SPECIFICATION_VERSION, OsName::Unix as u8])?;
+        w.write_all(&VERSION_ZIP64.to_le_bytes())?;
+        // this disk, and the disk containing the central directory
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&total_entries.to_le_bytes())?;
+        w.write_all(&total_entries.to_le_bytes())?;
+        w.write_all(&central_dir_size.to_le_bytes())?;
+        w.write_all(&central_dir_offset.to_le_bytes())?;
+
+        w.write_all(&ZIP64_END_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE)?;
+        // disk containing the zip64 end of central directory record
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&zip64_end_offset.to_le_bytes())?;
+        // total number of disks
+        w.write_all(&1u32.to_le_bytes())?;
+    }
+
+    let entries = total_entries.min(u64::from(u16::MAX)) as u16;
+
+    w.write_all(&END_CENTRAL_DIRECTORY_SIGNATURE)?;
+    // this disk, and the disk containing the central directory
+    w.write_all(&0u16.to_le_bytes())?;
+    w.write_all(&0u16.to_le_bytes())?;
+    w.write_all(&entries.to_le_bytes())?;
+    w.write_all(&entries.to_le_bytes())?;
+    w.write_all(&(central_dir_size.min(u64::from(u32::MAX)) as u32).to_le_bytes())?;
+    w.write_all(&(central_dir_offset.min(u64::from(u32::MAX)) as u32).to_le_bytes())?;
+    w.write_all(&(comment.len() as u16).to_le_bytes())?;
+    w.write_all(comment)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use crate::{
+        CompressionMethod, FileOptions, ZipArchive, ZipStreamWriter, ZipWriter,
+        CENTRAL_DIRECTORY_FILE_SIGNATURE, DATA_DESCRIPTOR_SIGNATURE,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        writer
+            .start_file(
+                "stored.txt",
+                FileOptions::default()
+                    .compression_method(CompressionMethod::STORED)
+                    .comment("a comment"),
+            )
+            .unwrap();
+        writer.write_all(b"hello world").unwrap();
+
+        writer.add_directory("dir", FileOptions::default()).unwrap();
+
+        writer
+            .start_file("dir/deflated.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(&b"abc".repeat(1000)).unwrap();
+
+        writer.set_comment("archive comment");
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        let headers = archive.central_directory().files;
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[0].comment, b"a comment");
+        assert_eq!(headers[1].metadata.name, b"dir/");
+        assert_eq!(
+            headers[2].metadata.compression_method,
+            CompressionMethod::DEFLATE
+        );
+
+        let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(
+            files[0].decompressed_contents().unwrap(),
+            &b"hello world"[..]
+        );
+        assert!(files[1].decompressed_contents().unwrap().is_empty());
+        assert_eq!(
+            files[2].decompressed_contents().unwrap(),
+            &b"abc".repeat(1000)[..]
+        );
+    }
+
+    #[test]
+    fn large_file_header() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        writer
+            .start_file("a", FileOptions::default().large_file(true))
+            .unwrap();
+        writer.write_all(b"zip64").unwrap();
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        let file = archive.files().next().unwrap().unwrap();
+        assert_eq!(file.decompressed_contents().unwrap(), &b"zip64"[..]);
+    }
+
+    #[test]
+    fn streaming() {
+        let mut writer = ZipStreamWriter::new(Vec::new());
+
+        writer.start_file("a.txt", FileOptions::default()).unwrap();
+        writer.write_all(b"streamed").unwrap();
+
+        writer
+            .start_file(
+                "b.txt",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+        writer.write_all(b"also streamed").unwrap();
+
+        let buffer = writer.finish().unwrap();
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert!(files[0].metadata.flags.has_data_descriptor());
+        assert_eq!(files[0].decompressed_contents().unwrap(), &b"streamed"[..]);
+        assert_eq!(
+            files[1].decompressed_contents().unwrap(),
+            &b"also streamed"[..]
+        );
+    }
+
+    #[test]
+    fn streaming_large_file() {
+        use std::io::Read;
+
+        use crate::ZipStreamReader;
+
+        let mut writer = ZipStreamWriter::new(Vec::new());
+
+        for name in ["a.txt", "b.txt"] {
+            writer
+                .start_file(name, FileOptions::default().large_file(true))
+                .unwrap();
+            writer.write_all(name.repeat(10).as_bytes()).unwrap();
+        }
+
+        // the zip64 descriptors can be read without the central directory
+        let buffer = writer.finish().unwrap();
+        let mut reader = ZipStreamReader::new(buffer.as_slice());
+
+        for name in ["a.txt", "b.txt"] {
+            let mut file = reader.next_entry().unwrap().unwrap();
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, name.repeat(10));
+        }
+
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+
+    /// Records everything written except a run of `zeros` zero bytes starting
+    /// at `skip_from`, so that huge files can be written without storing them
+    struct SparseSink {
+        written: Vec<u8>,
+        skip_from: u64,
+        zeros: u64,
+        pos: u64,
+    }
+
+    impl Write for SparseSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let end = self.pos + buf.len() as u64;
+            let skip_start = (self.skip_from.clamp(self.pos, end) - self.pos) as usize;
+            let skip_end = ((self.skip_from + self.zeros).clamp(self.pos, end) - self.pos) as usize;
+
+            assert!(buf[skip_start..skip_end].iter().all(|&b| b == 0));
+            self.written.extend_from_slice(&buf[..skip_start]);
+            self.written.extend_from_slice(&buf[skip_end..]);
+            self.pos = end;
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streaming_zip64_descriptor() {
+        let mut writer = ZipStreamWriter::new(Vec::new());
+
+        writer
+            .start_file(
+                "a",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+        writer.write_all(b"abc").unwrap();
+
+        // pretend far more was written than fits in 32 bits
+        let size = 5 * 1024 * 1024 * 1024;
+        writer.core.current.as_mut().unwrap().uncompressed_size = size;
+
+        let buffer = writer.finish().unwrap();
+
+        let descriptor = memchr::memmem::find(&buffer, &DATA_DESCRIPTOR_SIGNATURE).unwrap();
+        assert_eq!(buffer[descriptor + 8..descriptor + 16], 3u64.to_le_bytes());
+        assert_eq!(buffer[descriptor + 16..descriptor + 24], size.to_le_bytes());
+        assert_eq!(
+            buffer[descriptor + 24..descriptor + 28],
+            CENTRAL_DIRECTORY_FILE_SIGNATURE
+        );
+
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+        let header = archive.header(0).unwrap();
+        assert_eq!(header.metadata.compressed_size, 3);
+        assert_eq!(header.metadata.uncompressed_size, size);
+    }
+
+    #[test]
+    #[ignore = "streams over 4 GiB, which takes minutes without optimizations"]
+    fn streaming_zip64_without_large_file() {
+        use std::io::Read;
+
+        use crate::ZipStreamReader;
+
+        let len = u64::from(u32::MAX) + 10;
+        let zeros = vec![0; 1024 * 1024];
+
+        // the contents follow the 30 byte local header and the name
+        let mut writer = ZipStreamWriter::new(SparseSink {
+            written: Vec::new(),
+            skip_from: 31,
+            zeros: len,
+            pos: 0,
+        });
+
+        writer
+            .start_file(
+                "a",
+                FileOptions::default().compression_method(CompressionMethod::STORED),
+            )
+            .unwrap();
+
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(zeros.len() as u64) as usize;
+            writer.write_all(&zeros[..n]).unwrap();
+            remaining -= n as u64;
+        }
+
+        writer.start_file("b", FileOptions::default()).unwrap();
+        writer.write_all(b"after").unwrap();
+
+        let written = writer.finish().unwrap().written;
+        let archive = std::io::Cursor::new(&written[..31])
+            .chain(std::io::repeat(0).take(len))
+            .chain(&written[31..]);
+
+        let mut reader = ZipStreamReader::new(archive);
+
+        let mut file = reader.next_entry().unwrap().unwrap();
+        assert_eq!(std::io::copy(&mut file, &mut std::io::sink()).unwrap(), len);
+        drop(file);
+
+        let mut file = reader.next_entry().unwrap().unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"after");
+        drop(file);
+
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejected_options() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+        writer.start_file("a.txt", FileOptions::default()).unwrap();
+        writer.write_all(b"first").unwrap();
+
+        // bzip2 can be read but not written
+        assert!(matches!(
+            writer.start_file(
+                "b.txt",
+                FileOptions::default().compression_method(CompressionMethod(12))
+            ),
+            Err(crate::ZipParseError::UnsupportedCompression(
+                CompressionMethod(12)
+            ))
+        ));
+
+        writer.start_file("c.txt", FileOptions::default()).unwrap();
+        writer.write_all(b"third").unwrap();
+
+        let buffer = writer.finish().unwrap().into_inner();
+        let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+        let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].decompressed_contents().unwrap(), &b"first"[..]);
+        assert_eq!(files[1].metadata.name, b"c.txt");
+        assert_eq!(files[1].decompressed_contents().unwrap(), &b"third"[..]);
+    }
+
+    #[test]
+    #[cfg(any(feature = "zstd", feature = "xz"))]
+    fn zstd_and_xz() {
+        use std::io::Read;
+
+        use crate::ZipStreamReader;
+
+        let methods = [
+            #[cfg(feature = "zstd")]
+            CompressionMethod::ZSTD,
+            #[cfg(feature = "xz")]
+            CompressionMethod::XZ,
+        ];
+
+        for method in methods {
+            let options = FileOptions::default().compression_method(method);
+
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+            writer.start_file("a.txt", options.clone()).unwrap();
+            writer.write_all(&b"abc".repeat(1000)).unwrap();
+
+            let buffer = writer.finish().unwrap().into_inner();
+            let archive = ZipArchive::from_buffer(buffer).unwrap();
+
+            let file = archive.files().next().unwrap().unwrap();
+            assert_eq!(file.compression_method(), method);
+            assert!(file.compressed_contents().len() < 3000);
+            assert_eq!(
+                file.decompressed_contents().unwrap(),
+                &b"abc".repeat(1000)[..]
+            );
+
+            // with a data descriptor, the decoder alone finds the end of the
+            // contents
+            let mut writer = ZipStreamWriter::new(Vec::new());
+            writer.start_file("a.txt", options.clone()).unwrap();
+            writer.write_all(&b"abc".repeat(1000)).unwrap();
+            writer.start_file("b.txt", options).unwrap();
+            writer.write_all(b"second").unwrap();
+
+            let buffer = writer.finish().unwrap();
+            let mut reader = ZipStreamReader::new(buffer.as_slice());
+            let mut contents = Vec::new();
+
+            reader
+                .next_entry()
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            assert_eq!(contents, b"abc".repeat(1000));
+
+            contents.clear();
+            reader
+                .next_entry()
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            assert_eq!(contents, b"second");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "aes")]
+    fn aes_encryption() {
+        use std::io::Read;
+
+        use crate::{AesStrength, ZipParseError, ZipReader};
+
+        let methods = [CompressionMethod::STORED, CompressionMethod::DEFLATE];
+
+        for strength in [
+            AesStrength::Aes128,
+            AesStrength::Aes192,
+            AesStrength::Aes256,
+        ] {
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new())).unwrap();
+
+            for method in methods {
+                let options = FileOptions::default()
+                    .compression_method(method)
+                    .aes_encryption("secret", strength);
+
+                writer.start_file("a.txt", options).unwrap();
+                writer.write_all(&b"abc".repeat(1000)).unwrap();
+            }
+
+            let buffer = writer.finish().unwrap().into_inner();
+
+            assert!(memchr::memmem::find(&buffer, b"abcabc").is_none());
+
+            let archive = ZipArchive::from_buffer(buffer.as_slice()).unwrap();
+            let files = archive.files().collect::<Result<Vec<_>, _>>().unwrap();
+
+            for (file, method) in files.iter().zip(methods) {
+                assert_eq!(file.metadata.compression_method, CompressionMethod::AES);
+                assert_eq!(file.compression_method(), method);
+                assert_eq!(file.metadata.crc, 0);
+
+                assert!(matches!(
+                    file.decompressed_contents(),
+                    Err(ZipParseError::PasswordRequired)
+                ));
+                assert!(matches!(
+                    file.decompressed_contents_with_password(b"wrong"),
+                    Err(ZipParseError::InvalidPassword)
+                ));
+                assert_eq!(
+                    file.decompressed_contents_with_password(b"secret").unwrap(),
+                    &b"abc".repeat(1000)[..]
+                );
+            }
+
+            let mut reader = ZipReader::new(Cursor::new(buffer)).unwrap();
+            reader.set_password(b"secret");
+
+            let mut contents = Vec::new();
+            reader
+                .by_index(1)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            assert_eq!(contents, b"abc".repeat(1000));
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Traditional PKWARE encryption, also known as ZipCrypto.
+//!
+//! This is a stream cipher whose state is three 32-bit keys, initialized from
+//! the password. The contents of each file are preceded by a 12 byte header of
+//! random bytes, the last of which is used to check the password. The cipher
+//! is very weak, and is only supported to read existing archives.
+
+use std::io::{self, BufRead, Read};
+
+use crate::ZipParseError;
+
+/// Length of the encryption header preceding the encrypted contents
+pub(crate) const HEADER_LEN: usize = 12;
+
+/// The lookup table for the reflected CRC-32 polynomial used by ZIP
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    (crc >> 8) ^ CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize]
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Keys([u32; 3]);
+
+impl Keys {
+    pub(crate) fn new(password: &[u8]) -> Self {
+        let mut keys = Self([0x1234_5678, 0x2345_6789, 0x3456_7890]);
+
+        for &byte in password {
+            keys.update(byte);
+        }
+
+        keys
+    }
+
+    /// Mix a byte of plaintext into the keys
+    fn update(&mut self, byte: u8) {
+        let [k0, k1, k2] = &mut self.0;
+
+        *k0 = crc32_update(*k0, byte);
+        *k1 = k1
+            .wrapping_add(*k0 & 0xff)
+            .wrapping_mul(134_775_813)
+            .wrapping_add(1);
+        *k2 = crc32_update(*k2, (*k1 >> 24) as u8);
+    }
+
+    /// The next byte of the keystream
+    fn stream_byte(&self) -> u8 {
+        let temp = (self.0[2] | 2) as u16;
+
+        (temp.wrapping_mul(temp ^ 1) >> 8) as u8
+    }
+
+    pub(crate) fn decrypt(&mut self, bytes: &mut [u8]) {
+        for byte in bytes {
+            *byte ^= self.stream_byte();
+            self.update(*byte);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn encrypt(&mut self, bytes: &mut [u8]) {
+        for byte in bytes {
+            let plain = *byte;
+            *byte ^= self.stream_byte();
+            self.update(plain);
+        }
+    }
+}
+
+/// Decrypts the contents of a file as they are read.
+///
+/// Only the bytes consumed from this reader are consumed from the underlying
+/// one, so the underlying reader is left immediately after the last byte
+/// used, just like the decoders.
+#[derive(Debug)]
+pub(crate) struct ZipCryptoReader<R> {
+    inner: R,
+    keys: Keys,
+    /// Decrypted copies of the bytes available from `inner`
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> ZipCryptoReader<R> {
+    /// Read and decrypt the encryption header, returning
+    /// [`ZipParseError::InvalidPassword`] if its last byte isn't `check`
+    pub(crate) fn new(mut inner: R, password: &[u8], check: u8) -> Result<Self, ZipParseError> {
+        let mut keys = Keys::new(password);
+
+        let mut header = [0; HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        keys.decrypt(&mut header);
+
+        if header[HEADER_LEN - 1] != check {
+            return Err(ZipParseError::InvalidPassword);
+        }
+
+        Ok(Self {
+            inner,
+            keys,
+            buffer: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl<R: BufRead> Read for ZipCryptoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for ZipCryptoReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buffer.len() {
+            // everything decrypted so far has been consumed from `inner` too,
+            // so what it has available now follows on from it
+            let available = self.inner.fill_buf()?;
+
+            self.buffer.clear();
+            self.buffer.extend_from_slice(available);
+            self.keys.decrypt(&mut self.buffer);
+            self.pos = 0;
+        }
+
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.buffer.len() - self.pos);
+
+        self.pos += amt;
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::Read;
+
+    use crate::ZipParseError;
+
+    use super::{Keys, ZipCryptoReader, HEADER_LEN};
+
+    /// Encrypt `contents` with a header whose check byte is `check`
+    pub(crate) fn encrypt(contents: &[u8], password: &[u8], check: u8) -> Vec<u8> {
+        let mut out = vec![0x5a; HEADER_LEN];
+        out[HEADER_LEN - 1] = check;
+        out.extend_from_slice(contents);
+
+        Keys::new(password).encrypt(&mut out);
+
+        out
+    }
+
+    #[test]
+    fn decrypt() {
+        let contents = b"hello world\n".repeat(100);
+        let encrypted = encrypt(&contents, b"secret", 0xab);
+
+        let mut reader = ZipCryptoReader::new(&encrypted[..], b"secret", 0xab).unwrap();
+
+        // small reads exercise decrypting the buffer in pieces
+        let mut decrypted = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decrypted.extend_from_slice(&buf[..n]);
+        }
+
+        assert_eq!(decrypted, contents);
+
+        assert!(matches!(
+            ZipCryptoReader::new(&encrypted[..], b"wrong", 0xab),
+            Err(ZipParseError::InvalidPassword)
+        ));
+    }
+}